@@ -0,0 +1,341 @@
+use std::time::{Duration, Instant, SystemTime};
+use reqwest::{header::ACCEPT, StatusCode};
+use serde_json::{json, Value};
+use crate::checks::{self, IcingaObject, SubmitOptions};
+use crate::config::IcingaConfig;
+use crate::{control, pings};
+
+/// Author of downtimes and acknowledgements unless one is given.
+pub const DEFAULT_AUTHOR: &str = "icinga_passive_checks";
+
+/// The URL of another API action next to a configured
+/// `.../v1/actions/process-check-result` one.
+fn action_url(url: &str, action: &str) -> String {
+    match url.rsplit_once('/') {
+        Some((base, _)) => format!("{}/{}", base, action),
+        None => action.to_string(),
+    }
+}
+
+/// Post an action to the Icinga API, failing over between the endpoints
+/// like result submissions do. Returns the `results` of the answer.
+pub fn post_action(config: &IcingaConfig, action: &str, body: &Value) -> Result<Vec<Value>, String> {
+    let endpoints = &config.endpoints;
+    if endpoints.urls().is_empty() {
+        return Err("Missing 'api_url' in the icinga section".to_string());
+    }
+
+    let mut errors = Vec::new();
+    for index in endpoints.candidates(Instant::now()) {
+        let url = action_url(&endpoints.urls()[index], action);
+        let mut request = config.client
+            .post(&url)
+            .header(ACCEPT, "application/json")
+            .json(body);
+        if !config.api_user.is_empty() {
+            request = request.basic_auth(&config.api_user, Some(&config.api_password));
+        }
+        let error = match request.send() {
+            Ok(response) => {
+                let status = response.status();
+                let body: Value = response.json().unwrap_or(Value::Null);
+                // Icinga's own answers have results or a status, anything
+                // else failing is the endpoint's problem
+                if status.is_server_error() && body.get("results").is_none() && body.get("status").is_none() {
+                    format!("Icinga returned {}", status)
+                } else {
+                    endpoints.record_success(index);
+                    return action_results(status, &body);
+                }
+            }
+            Err(e) => checks::request_error(&e),
+        };
+        endpoints.record_failure(index, &error, Instant::now());
+        errors.push(if endpoints.urls().len() > 1 { format!("{}: {}", url, error) } else { error });
+    }
+    Err(errors.join("; "))
+}
+
+/// Icinga answers with one entry per matched object in `results`, each with
+/// its own `code` and `status`, or with just a `status` if nothing matched.
+fn action_results(status: StatusCode, body: &Value) -> Result<Vec<Value>, String> {
+    let Some(results) = body["results"].as_array() else {
+        return Err(body["status"].as_str()
+            .map(String::from)
+            .unwrap_or_else(|| format!("Icinga returned {}", status)));
+    };
+    let errors: Vec<&str> = results.iter()
+        .filter(|result| result["code"].as_f64() != Some(200.0))
+        .map(|result| result["status"].as_str().unwrap_or("failed"))
+        .collect();
+    if !errors.is_empty() {
+        Err(errors.join("; "))
+    } else if results.is_empty() || !status.is_success() {
+        Err(format!("Icinga returned {}", status))
+    } else {
+        Ok(results.clone())
+    }
+}
+
+/// The user running a command, for the author of downtimes and
+/// acknowledgements.
+pub fn default_author() -> String {
+    ["SUDO_USER", "USER"].iter()
+        .find_map(|var| std::env::var(var).ok().filter(|user| !user.is_empty()))
+        .unwrap_or_else(|| DEFAULT_AUTHOR.to_string())
+}
+
+/// Parse a duration like `90`, `30m`, `1h30m` or `2d`, plain numbers are
+/// seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{}', expected e.g. 90, 30m, 1h30m or 2d", duration);
+    if let Ok(secs) = duration.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        secs += number.parse::<u64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+    if !number.is_empty() || secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// The Icinga object a configured ping or report submits its results for.
+pub fn check_target(config: &IcingaConfig, name: &str) -> Option<IcingaObject> {
+    let hostname = crate::get_hostname();
+    if let Some(ping) = config.pings.iter().find(|ping| ping.name == name) {
+        Some(checks::check_object(&hostname, name, &ping.host, pings::CHECK_TYPE, &ping.submit, &config.names))
+    } else {
+        config.reports.iter().find(|report| report.name == name).map(|report| {
+            checks::check_object(&hostname, name, &hostname, control::CHECK_TYPE, &report.submit, &config.names)
+        })
+    }
+}
+
+/// The Icinga objects a command is about: configured checks by name,
+/// services of this host by their Icinga name, or this host itself if
+/// neither is given.
+pub fn resolve_targets(config: &IcingaConfig, check_names: &[String], services: &[String]) -> Result<Vec<IcingaObject>, String> {
+    let hostname = crate::get_hostname();
+    let host = checks::check_object(&hostname, "", &hostname, "", &SubmitOptions::default(), &config.names).host;
+    if check_names.is_empty() && services.is_empty() {
+        return Ok(vec![IcingaObject { host, service: None }]);
+    }
+
+    let mut objects = Vec::new();
+    for name in check_names {
+        objects.push(check_target(config, name).ok_or_else(|| {
+            format!("unknown check '{}', use --service for services that aren't configured checks", name)
+        })?);
+    }
+    objects.extend(services.iter().map(|service| IcingaObject { host: host.clone(), service: Some(service.clone()) }));
+    Ok(objects)
+}
+
+/// The `schedule-downtime` request for a fixed downtime of a host or
+/// service. With `all_services` a host's services get one too.
+fn downtime_body(
+    object: &IcingaObject,
+    start: SystemTime,
+    end: SystemTime,
+    author: &str,
+    comment: &str,
+    all_services: bool,
+) -> Value {
+    let (object_type, filter) = checks::object_filter(object);
+    let mut body = json!({
+        "type": object_type,
+        "filter": filter,
+        "start_time": checks::unix_time(start),
+        "end_time": checks::unix_time(end),
+        "fixed": true,
+        "author": author,
+        "comment": comment,
+    });
+    if all_services && object.service.is_none() {
+        body["all_services"] = Value::Bool(true);
+    }
+    body
+}
+
+/// Schedule a fixed downtime for a host or service, returning the names of
+/// the created downtimes.
+pub fn schedule_downtime(
+    config: &IcingaConfig,
+    object: &IcingaObject,
+    start: SystemTime,
+    end: SystemTime,
+    author: &str,
+    comment: &str,
+    all_services: bool,
+) -> Result<Vec<String>, String> {
+    let body = downtime_body(object, start, end, author, comment, all_services);
+    let results = post_action(config, "schedule-downtime", &body)
+        .map_err(|e| format!("Failed to schedule a downtime for {}: {}", object, e))?;
+    Ok(results.iter().filter_map(|result| result["name"].as_str().map(String::from)).collect())
+}
+
+/// Filter matching the downtimes of a service, or of a host and with
+/// `all_services` also those of its services.
+fn downtime_filter(object: &IcingaObject, all_services: bool) -> String {
+    let host = format!("downtime.host_name=={}", checks::quote_filter_string(&object.host));
+    match (&object.service, all_services) {
+        (Some(service), _) => format!("{} && downtime.service_name=={}", host, checks::quote_filter_string(service)),
+        (None, true) => host,
+        (None, false) => format!("{} && downtime.service_name==\"\"", host),
+    }
+}
+
+/// Remove the downtimes of a host or service, returning how many were removed.
+pub fn remove_downtimes(config: &IcingaConfig, object: &IcingaObject, all_services: bool) -> Result<usize, String> {
+    let body = json!({ "type": "Downtime", "filter": downtime_filter(object, all_services) });
+    match post_action(config, "remove-downtime", &body) {
+        Ok(results) => Ok(results.len()),
+        Err(e) if e == "No objects found." => Ok(0),
+        Err(e) => Err(format!("Failed to remove the downtimes of {}: {}", object, e)),
+    }
+}
+
+/// The `acknowledge-problem` request for a host or service. A sticky
+/// acknowledgement lasts until the object is OK again instead of ending
+/// with the next state change, `expiry` removes it at that time.
+fn acknowledge_body(object: &IcingaObject, author: &str, comment: &str, sticky: bool, expiry: Option<SystemTime>) -> Value {
+    let (object_type, filter) = checks::object_filter(object);
+    let mut body = json!({
+        "type": object_type,
+        "filter": filter,
+        "author": author,
+        "comment": comment,
+        "sticky": sticky,
+        "notify": true,
+    });
+    if let Some(expiry) = expiry {
+        body["expiry"] = checks::unix_time(expiry);
+    }
+    body
+}
+
+/// Acknowledge the current problem of a host or service.
+pub fn acknowledge_problem(
+    config: &IcingaConfig,
+    object: &IcingaObject,
+    author: &str,
+    comment: &str,
+    sticky: bool,
+    expiry: Option<SystemTime>,
+) -> Result<(), String> {
+    post_action(config, "acknowledge-problem", &acknowledge_body(object, author, comment, sticky, expiry))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to acknowledge the problem of {}: {}", object, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_url() {
+        assert_eq!(
+            action_url("https://icinga:5665/v1/actions/process-check-result", "schedule-downtime"),
+            "https://icinga:5665/v1/actions/schedule-downtime"
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("0m").is_err());
+    }
+
+    #[test]
+    fn test_downtime_body() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000);
+        let end = start + Duration::from_secs(3600);
+        let service = IcingaObject { host: "server1".to_string(), service: Some("Passive Ping: router".to_string()) };
+        let body = downtime_body(&service, start, end, "alice", "Replacing the router", true);
+        assert_eq!(body["type"], Value::from("Service"));
+        assert_eq!(body["filter"], Value::from("host.name==\"server1\" && service.name==\"Passive Ping: router\""));
+        assert_eq!(body["start_time"], Value::from(1700000000.0));
+        assert_eq!(body["end_time"], Value::from(1700003600.0));
+        assert_eq!(body["fixed"], Value::Bool(true));
+        assert_eq!(body["author"], Value::from("alice"));
+        assert_eq!(body["comment"], Value::from("Replacing the router"));
+        // Only hosts have services to cover
+        assert!(body.get("all_services").is_none());
+
+        let host = IcingaObject { host: "server1".to_string(), service: None };
+        let body = downtime_body(&host, start, end, "alice", "Reboot", true);
+        assert_eq!(body["type"], Value::from("Host"));
+        assert_eq!(body["filter"], Value::from("host.name==\"server1\""));
+        assert_eq!(body["all_services"], Value::Bool(true));
+        assert!(downtime_body(&host, start, end, "alice", "Reboot", false).get("all_services").is_none());
+    }
+
+    #[test]
+    fn test_downtime_filter() {
+        let host = IcingaObject { host: "server1".to_string(), service: None };
+        assert_eq!(downtime_filter(&host, false), "downtime.host_name==\"server1\" && downtime.service_name==\"\"");
+        assert_eq!(downtime_filter(&host, true), "downtime.host_name==\"server1\"");
+        let service = IcingaObject { host: "server1".to_string(), service: Some("Passive Ping: router".to_string()) };
+        assert_eq!(
+            downtime_filter(&service, false),
+            "downtime.host_name==\"server1\" && downtime.service_name==\"Passive Ping: router\""
+        );
+    }
+
+    #[test]
+    fn test_acknowledge_body() {
+        let service = IcingaObject { host: "server1".to_string(), service: Some("backup".to_string()) };
+        let body = acknowledge_body(&service, "alice", "rebuilding|sticky", false, None);
+        assert_eq!(body["type"], Value::from("Service"));
+        assert_eq!(body["filter"], Value::from("host.name==\"server1\" && service.name==\"backup\""));
+        assert_eq!(body["author"], Value::from("alice"));
+        assert_eq!(body["comment"], Value::from("rebuilding|sticky"));
+        assert_eq!(body["sticky"], Value::Bool(false));
+        assert_eq!(body["notify"], Value::Bool(true));
+        assert!(body.get("expiry").is_none());
+
+        let host = IcingaObject { host: "server1".to_string(), service: None };
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(1700001800);
+        let body = acknowledge_body(&host, "alice", "Disk swap", true, Some(expiry));
+        assert_eq!(body["type"], Value::from("Host"));
+        assert_eq!(body["filter"], Value::from("host.name==\"server1\""));
+        assert_eq!(body["sticky"], Value::Bool(true));
+        assert_eq!(body["expiry"], Value::from(1700001800.0));
+    }
+
+    #[test]
+    fn test_action_results() {
+        let body = json!({"results": [{"code": 200.0, "name": "server1!ping!1", "status": "Successfully scheduled downtime"}]});
+        assert_eq!(action_results(StatusCode::OK, &body).unwrap().len(), 1);
+
+        let body = json!({"results": [
+            {"code": 200.0, "status": "ok"},
+            {"code": 409.0, "status": "Object is not in a problem state"},
+        ]});
+        assert_eq!(action_results(StatusCode::INTERNAL_SERVER_ERROR, &body).unwrap_err(), "Object is not in a problem state");
+
+        let body = json!({"error": 404.0, "status": "No objects found."});
+        assert_eq!(action_results(StatusCode::NOT_FOUND, &body).unwrap_err(), "No objects found.");
+    }
+}
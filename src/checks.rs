@@ -1,95 +1,657 @@
 use crate::config::IcingaConfig;
+use crate::naming::{self, NameTemplates, NameVars};
+use crate::tls::{self, TlsConfig};
 use reqwest::{blocking::Client, header::ACCEPT, StatusCode};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub type CheckResult = HashMap<String, String>;
+pub use crate::perfdata::PerfData;
 
-const DEFAULT_EXIT_STATUS: i32 = 3; // UNKNOWN status code
+/// Service and host states as used by the plugin API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl State {
+    /// Map an exit code to a state, anything out of range is UNKNOWN.
+    pub fn from_code(code: i32) -> State {
+        match code {
+            0 => State::Ok,
+            1 => State::Warning,
+            2 => State::Critical,
+            _ => State::Unknown,
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        match self {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Critical => 2,
+            State::Unknown => 3,
+        }
+    }
+
+    /// Exit status for a host check result, Icinga only knows UP (0) and
+    /// DOWN (1) and maps WARNING to UP and UNKNOWN to DOWN.
+    pub fn host_code(self) -> i32 {
+        match self {
+            State::Ok | State::Warning => 0,
+            State::Critical | State::Unknown => 1,
+        }
+    }
+
+    /// Combine two states, keeping the more severe one. UNKNOWN ranks
+    /// between WARNING and CRITICAL, as it does in Icinga.
+    pub fn worst(self, other: State) -> State {
+        let severity = |state: State| match state {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Unknown => 2,
+            State::Critical => 3,
+        };
+        if severity(other) > severity(self) { other } else { self }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub state: State,
+    pub output: String,
+    pub long_output: Option<String>,
+    pub perfdata: Vec<PerfData>,
+    pub execution_start: SystemTime,
+    pub execution_end: SystemTime,
+}
+
+impl CheckResult {
+    /// A result for something that happened just now, checks that take
+    /// time should set `execution_start` themselves.
+    pub fn new(state: State, output: &str) -> Self {
+        let now = SystemTime::now();
+        CheckResult {
+            state,
+            output: output.to_string(),
+            long_output: None,
+            perfdata: Vec::new(),
+            execution_start: now,
+            execution_end: now,
+        }
+    }
+
+    /// Output and long output joined the way plugins print them.
+    pub fn plugin_output(&self) -> String {
+        match &self.long_output {
+            Some(long_output) => format!("{}\n{}", self.output, long_output),
+            None => self.output.clone(),
+        }
+    }
+}
+
+/// Which kind of Icinga object a result is submitted for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SubmitAs {
+    /// A service, `{type}: {name}` on this host unless configured otherwise
+    #[default]
+    Service,
+    /// A host, by default the one named after the check, e.g. an appliance
+    /// that is pinged
+    Host,
+}
+
+/// Per-check settings for the optional process-check-result fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubmitOptions {
+    pub submit_as: SubmitAs,
+    /// Seconds until Icinga considers the result stale
+    pub ttl: Option<u64>,
+    /// Command line shown in Icinga as the one that produced the result
+    pub check_command: Option<Vec<String>>,
+    /// Per-check name templates, taking precedence over the global ones
+    pub names: NameTemplates,
+    /// Sinks the results go to instead of the global default
+    pub sinks: Option<Vec<String>>,
+}
+
+impl SubmitOptions {
+    /// Host and service name templates for this check. A global host name
+    /// template only applies to services, host checks default to the host
+    /// named after the check.
+    fn templates<'a>(&'a self, global: &'a NameTemplates) -> (&'a str, &'a str) {
+        let host_name = self.names.host_name.as_deref().unwrap_or(match self.submit_as {
+            SubmitAs::Service => global.host_name.as_deref().unwrap_or(naming::DEFAULT_HOST_NAME),
+            SubmitAs::Host => naming::DEFAULT_CHECK_HOST_NAME,
+        });
+        let service_name = self.names.service_name.as_deref()
+            .or(global.service_name.as_deref())
+            .unwrap_or(naming::DEFAULT_SERVICE_NAME);
+        (host_name, service_name)
+    }
+}
+
+/// How the Icinga object a result belongs to is addressed in the request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressBy {
+    /// A `filter` expression matching the host and service name
+    #[default]
+    Filter,
+    /// The `host` or `service` (`host!service`) object name parameters
+    Name,
+}
+
+/// The host or service a result is submitted for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcingaObject {
+    pub host: String,
+    pub service: Option<String>,
+}
+
+impl std::fmt::Display for IcingaObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.service {
+            Some(service) => write!(f, "service '{}' on host '{}'", service, self.host),
+            None => write!(f, "host '{}'", self.host),
+        }
+    }
+}
+
+/// Quote a string for use in an Icinga DSL filter expression.
+pub fn quote_filter_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The object type and filter expression matching an object in the Icinga API.
+pub fn object_filter(object: &IcingaObject) -> (&'static str, String) {
+    match &object.service {
+        Some(service) => (
+            "Service",
+            format!("host.name=={} && service.name=={}", quote_filter_string(&object.host), quote_filter_string(service)),
+        ),
+        None => ("Host", format!("host.name=={}", quote_filter_string(&object.host))),
+    }
+}
+
+/// Describe a failed request including its causes, reqwest's own message
+/// doesn't say why, e.g. which TLS check failed.
+pub fn request_error(e: &reqwest::Error) -> String {
+    let mut reason = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        let cause_text = cause.to_string();
+        if !reason.ends_with(&cause_text) {
+            reason = format!("{}: {}", reason, cause_text);
+        }
+        source = cause.source();
+    }
+    format!("request failed: {}", reason)
+}
 
 type CheckPayload = HashMap<String, Value>;
 
-fn parse_performance_data(perf_data: Option<&String>) -> Vec<String> {
-    perf_data
-        .map(|data| data.split(',').map(|s| s.trim().to_string()).collect())
-        .unwrap_or_default()
+/// Counters and latency of the requests to the Icinga endpoints since the
+/// daemon started. A result that fails over is one request per endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubmissionStats {
+    pub sent: u64,
+    pub failed: u64,
+    pub total_latency: Duration,
+    pub last_latency: Option<Duration>,
+    pub max_latency: Duration,
 }
 
-fn format_check_payload(
+impl SubmissionStats {
+    const fn new() -> Self {
+        SubmissionStats {
+            sent: 0,
+            failed: 0,
+            total_latency: Duration::ZERO,
+            last_latency: None,
+            max_latency: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, latency: Duration, success: bool) {
+        self.sent += 1;
+        if !success {
+            self.failed += 1;
+        }
+        self.total_latency += latency;
+        self.last_latency = Some(latency);
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    pub fn average_latency(&self) -> Option<Duration> {
+        u32::try_from(self.sent).ok()
+            .filter(|&sent| sent > 0)
+            .map(|sent| self.total_latency / sent)
+    }
+}
+
+static STATS: Mutex<SubmissionStats> = Mutex::new(SubmissionStats::new());
+
+pub fn submission_stats() -> SubmissionStats {
+    *STATS.lock().unwrap()
+}
+
+/// Build the HTTP client used for all submissions. It is kept in the config
+/// and reused so requests share keep-alive connections instead of doing a
+/// TLS handshake per result.
+pub fn build_client(tls: &TlsConfig) -> Result<Client, String> {
+    let mut builder = Client::builder();
+    if !tls.is_default() {
+        builder = builder.use_preconfigured_tls(tls::client_config(tls)?);
+    }
+    builder.build().map_err(|e| format!("Failed to create the HTTP client: {}", e))
+}
+
+pub fn unix_time(time: SystemTime) -> Value {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default();
+    serde_json::Number::from_f64(secs).map(Value::Number).unwrap_or(Value::Null)
+}
+
+/// The host or service a check's results are submitted for, after applying
+/// the name templates.
+pub fn check_object(
     check_source: &str,
-    check_type: &str,
     check_name: &str,
+    check_host: &str,
+    check_type: &str,
+    options: &SubmitOptions,
+    global_names: &NameTemplates,
+) -> IcingaObject {
+    let fqdn = crate::get_fqdn();
+    let vars = NameVars {
+        hostname: check_source,
+        fqdn: &fqdn,
+        check_type,
+        name: check_name,
+        host: check_host,
+    };
+    resolve_object(&vars, options, global_names)
+}
+
+fn resolve_object(vars: &NameVars, options: &SubmitOptions, global_names: &NameTemplates) -> IcingaObject {
+    let (host_template, service_template) = options.templates(global_names);
+    IcingaObject {
+        host: naming::render(host_template, vars),
+        service: match options.submit_as {
+            SubmitAs::Service => Some(naming::render(service_template, vars)),
+            SubmitAs::Host => None,
+        },
+    }
+}
+
+fn format_check_payload(
+    object: &IcingaObject,
+    check_source: &str,
     check_data: &CheckResult,
-) -> CheckPayload {
-    let filter_value = format!(
-        "host.name==\"{}\" && service.name==\"{}: {}\"",
-        check_source, check_type, check_name
-    );
-
-    let exit_status = check_data
-        .get("exit_status")
-        .and_then(|s| s.parse::<i32>().ok())
-        .unwrap_or(DEFAULT_EXIT_STATUS);
-
-    let plugin_output = check_data
-        .get("plugin_output")
-        .cloned()
-        .unwrap_or_else(|| "No output provided".to_string());
-
-    let perf_data = parse_performance_data(check_data.get("performance_data"));
-
-    HashMap::from([
-        ("type".to_string(), Value::String("Service".to_string())),
-        ("filter".to_string(), Value::String(filter_value)),
+    options: &SubmitOptions,
+    address_by: AddressBy,
+) -> Result<CheckPayload, String> {
+    let (object_type, filter) = object_filter(object);
+    let exit_status = match object.service {
+        Some(_) => check_data.state.code(),
+        None => check_data.state.host_code(),
+    };
+
+    let address = match (address_by, &object.service) {
+        (AddressBy::Filter, _) => ("filter", filter),
+        // Icinga separates host and service with '!' and doesn't allow it in host names
+        (AddressBy::Name, _) if object.host.contains('!') => {
+            return Err(format!("{} can't be addressed by name, the host name contains '!'", object));
+        }
+        (AddressBy::Name, Some(service)) => ("service", format!("{}!{}", object.host, service)),
+        (AddressBy::Name, None) => ("host", object.host.clone()),
+    };
+
+    let mut payload = HashMap::from([
+        ("type".to_string(), Value::String(object_type.to_string())),
+        (address.0.to_string(), Value::String(address.1)),
         ("exit_status".to_string(), Value::Number(exit_status.into())),
-        ("plugin_output".to_string(), Value::String(plugin_output)),
+        ("plugin_output".to_string(), Value::String(check_data.plugin_output())),
         (
             "performance_data".to_string(),
-            Value::Array(perf_data.into_iter().map(Value::String).collect()),
+            Value::Array(check_data.perfdata.iter().map(|p| Value::String(p.to_string())).collect()),
         ),
         (
             "check_source".to_string(),
             Value::String(check_source.to_string()),
         ),
-    ])
+        ("execution_start".to_string(), unix_time(check_data.execution_start)),
+        ("execution_end".to_string(), unix_time(check_data.execution_end)),
+    ]);
+
+    if let Some(ttl) = options.ttl {
+        payload.insert("ttl".to_string(), Value::Number(ttl.into()));
+    }
+    if let Some(check_command) = &options.check_command {
+        payload.insert(
+            "check_command".to_string(),
+            Value::Array(check_command.iter().cloned().map(Value::String).collect()),
+        );
+    }
+
+    Ok(payload)
 }
 
+/// Submit a check result to Icinga, returning an error describing why the
+/// submission failed so callers can keep track of it.
 pub fn send_passive_check(
     check_source: &str,
     check_name: &str,
     check_host: &str,
     check_type: &str,
     check_data: &CheckResult,
+    options: &SubmitOptions,
     icinga_config: &IcingaConfig,
-) {
-    let client = Client::new();
-    let data = format_check_payload(check_source, check_type, check_name, check_data);
+) -> Result<(), String> {
+    let object = check_object(check_source, check_name, check_host, check_type, options, &icinga_config.names);
+    let data = format_check_payload(&object, check_source, check_data, options, icinga_config.address_by)
+        .inspect_err(|e| println!("Failed to send passive check result: {}", e))?;
 
-    let response = client
-        .post(&icinga_config.api_url)
-        .basic_auth(&icinga_config.api_user, Some(&icinga_config.api_password))
+    let endpoints = &icinga_config.endpoints;
+    let mut errors = Vec::new();
+    for index in endpoints.candidates(Instant::now()) {
+        let url = &endpoints.urls()[index];
+        match post_result(icinga_config, url, &data, &object) {
+            Ok(latency) => {
+                endpoints.record_success(index);
+                println!(
+                    "Successfully sent passive check result for host {} check {} host {} in {}ms",
+                    check_source, check_name, check_host, latency.as_millis()
+                );
+                return Ok(());
+            }
+            Err(SendError::Rejected(e)) => {
+                endpoints.record_success(index);
+                println!(
+                    "Failed to send passive check result for host {} check {}: {}",
+                    check_source, check_name, e
+                );
+                return Err(e);
+            }
+            Err(SendError::Endpoint(e)) => {
+                endpoints.record_failure(index, &e, Instant::now());
+                println!(
+                    "Failed to send passive check result for host {} check {} to {}: {}",
+                    check_source, check_name, url, e
+                );
+                errors.push(if endpoints.urls().len() > 1 { format!("{}: {}", url, e) } else { e });
+            }
+        }
+    }
+    Err(errors.join("; "))
+}
+
+/// Why a submission to one endpoint failed.
+enum SendError {
+    /// The endpoint couldn't be reached or failed itself, another one may
+    /// accept the result
+    Endpoint(String),
+    /// Icinga rejected the result, no other endpoint will accept it either
+    Rejected(String),
+}
+
+/// Post a result to one endpoint, returning how long Icinga took to answer.
+fn post_result(
+    icinga_config: &IcingaConfig,
+    url: &str,
+    data: &CheckPayload,
+    object: &IcingaObject,
+) -> Result<Duration, SendError> {
+    let started = Instant::now();
+    let mut request = icinga_config.client
+        .post(url)
         .header(ACCEPT, "application/json")
-        .json(&data)
-        .send()
-        .unwrap();
+        .json(data);
+    if !icinga_config.api_user.is_empty() {
+        request = request.basic_auth(&icinga_config.api_user, Some(&icinga_config.api_password));
+    }
+    let response = request.send();
+    let latency = started.elapsed();
+    STATS.lock().unwrap().record(latency, matches!(&response, Ok(r) if r.status() == StatusCode::OK));
+
+    let response = response.map_err(|e| SendError::Endpoint(request_error(&e)))?;
 
     let status = response.status();
     if status == StatusCode::OK {
-        println!(
-            "Successfully sent passive check result for host {} check {} host {}",
-            check_source, check_name, check_host
-        );
+        Ok(latency)
+    } else if status == StatusCode::NOT_FOUND {
+        // Icinga answers {"error": 404, "status": "No objects found."} when
+        // the host or service doesn't exist, usually a naming mismatch
+        let error_body = response.text().unwrap_or_default();
+        let reason = serde_json::from_str::<Value>(&error_body).ok()
+            .and_then(|body| body.get("status").and_then(|s| s.as_str()).map(String::from))
+            .unwrap_or(error_body);
+        Err(SendError::Rejected(format!("Icinga has no {} ({})", object, reason)))
     } else {
-        let error_body = response.text().unwrap();
-        println!(
-            "Failed to send passive check result for host {} check {}",
-            check_source, check_host
-        );
+        let error_body = response.text().unwrap_or_default();
         println!("Status: {}", status);
         println!("Response body: {}", error_body);
         println!(
             "Request data was: {}",
-            serde_json::to_string_pretty(&data).unwrap()
+            serde_json::to_string_pretty(data).unwrap()
         );
+        // 5xx, e.g. a master that is restarting, is worth trying elsewhere
+        let error = format!("Icinga returned {}", status);
+        if status.is_server_error() {
+            Err(SendError::Endpoint(error))
+        } else {
+            Err(SendError::Rejected(error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(check_type: &'a str, name: &'a str) -> NameVars<'a> {
+        NameVars {
+            hostname: "server1",
+            fqdn: "server1.example.com",
+            check_type,
+            name,
+            host: "192.168.1.1",
+        }
+    }
+
+    fn build_payload(
+        vars: &NameVars,
+        result: &CheckResult,
+        options: &SubmitOptions,
+        global: &NameTemplates,
+        address_by: AddressBy,
+    ) -> CheckPayload {
+        let object = resolve_object(vars, options, global);
+        format_check_payload(&object, vars.hostname, result, options, address_by).unwrap()
+    }
+
+    #[test]
+    fn test_state_from_code() {
+        assert_eq!(State::from_code(0), State::Ok);
+        assert_eq!(State::from_code(2), State::Critical);
+        assert_eq!(State::from_code(42), State::Unknown);
+        assert_eq!(State::from_code(-1), State::Unknown);
+        assert_eq!(State::Warning.code(), 1);
+    }
+
+    #[test]
+    fn test_format_check_payload_host() {
+        let result = CheckResult::new(State::Critical, "PING CRITICAL - Packet loss = 100%");
+        let options = SubmitOptions { submit_as: SubmitAs::Host, ..Default::default() };
+
+        let payload = build_payload(&vars("Passive Ping", "appliance"), &result, &options, &NameTemplates::default(), AddressBy::Filter);
+        assert_eq!(payload["type"], Value::from("Host"));
+        assert_eq!(payload["filter"], Value::from("host.name==\"appliance\""));
+        assert_eq!(payload["exit_status"], Value::from(1));
+        assert_eq!(payload["check_source"], Value::from("server1"));
+    }
+
+    #[test]
+    fn test_format_check_payload_name_templates() {
+        let result = CheckResult::new(State::Ok, "PING OK");
+        let global = NameTemplates {
+            service_name: Some("ping-{name}".to_string()),
+            host_name: Some("{fqdn}".to_string()),
+        };
+
+        let payload = build_payload(&vars("Passive Ping", "router"), &result, &SubmitOptions::default(), &global, AddressBy::Filter);
+        assert_eq!(
+            payload["filter"],
+            Value::from("host.name==\"server1.example.com\" && service.name==\"ping-router\"")
+        );
+
+        // Per-check templates win over the global ones
+        let options = SubmitOptions {
+            names: NameTemplates { service_name: Some("{name} {host}".to_string()), host_name: None },
+            ..Default::default()
+        };
+        let payload = build_payload(&vars("Passive Ping", "router"), &result, &options, &global, AddressBy::Filter);
+        assert_eq!(
+            payload["filter"],
+            Value::from("host.name==\"server1.example.com\" && service.name==\"router 192.168.1.1\"")
+        );
+
+        // The global host name doesn't redirect host checks to this host
+        let options = SubmitOptions { submit_as: SubmitAs::Host, ..Default::default() };
+        let payload = build_payload(&vars("Passive Ping", "nas"), &result, &options, &global, AddressBy::Filter);
+        assert_eq!(payload["filter"], Value::from("host.name==\"nas\""));
+    }
+
+    #[test]
+    fn test_state_host_code() {
+        assert_eq!(State::Ok.host_code(), 0);
+        assert_eq!(State::Warning.host_code(), 0);
+        assert_eq!(State::Critical.host_code(), 1);
+        assert_eq!(State::Unknown.host_code(), 1);
+    }
+
+    #[test]
+    fn test_state_worst() {
+        assert_eq!(State::Ok.worst(State::Warning), State::Warning);
+        assert_eq!(State::Critical.worst(State::Unknown), State::Critical);
+        assert_eq!(State::Unknown.worst(State::Warning), State::Unknown);
+    }
+
+    #[test]
+    fn test_format_check_payload() {
+        let mut result = CheckResult::new(State::Warning, "DISK WARNING");
+        result.long_output = Some("/var 91%".to_string());
+        let mut perf = PerfData::new("var", 91.0, "%");
+        crate::threshold::Thresholds::new(Some("90"), Some("95")).unwrap().apply(&mut perf);
+        result.perfdata.push(perf);
+
+        let payload = build_payload(&vars("Passive Command", "disk"), &result, &SubmitOptions::default(), &NameTemplates::default(), AddressBy::Filter);
+        assert_eq!(payload["exit_status"], Value::from(1));
+        assert_eq!(payload["plugin_output"], Value::from("DISK WARNING\n/var 91%"));
+        assert_eq!(payload["performance_data"], Value::from(vec!["var=91%;90;95"]));
+        assert_eq!(
+            payload["filter"],
+            Value::from("host.name==\"server1\" && service.name==\"Passive Command: disk\"")
+        );
+        assert!(payload["execution_start"].as_f64().unwrap() > 0.0);
+        assert!(!payload.contains_key("ttl"));
+        assert!(!payload.contains_key("check_command"));
+    }
+
+    #[test]
+    fn test_format_check_payload_options() {
+        let mut result = CheckResult::new(State::Ok, "PING OK");
+        result.execution_start = UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_250);
+        result.execution_end = UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_007_500);
+        let options = SubmitOptions {
+            submit_as: SubmitAs::Service,
+            ttl: Some(600),
+            check_command: Some(vec!["ping".to_string(), "-c".to_string(), "8".to_string()]),
+            names: NameTemplates::default(),
+            sinks: None,
+        };
+
+        let payload = build_payload(&vars("Passive Ping", "router"), &result, &options, &NameTemplates::default(), AddressBy::Filter);
+        assert_eq!(payload["ttl"], Value::from(600));
+        assert_eq!(payload["check_command"], Value::from(vec!["ping", "-c", "8"]));
+        assert_eq!(payload["execution_start"], Value::from(1_700_000_000.25));
+        assert_eq!(payload["execution_end"], Value::from(1_700_000_007.5));
+    }
+
+    #[test]
+    fn test_filter_quotes_names() {
+        assert_eq!(quote_filter_string("router"), "\"router\"");
+        assert_eq!(quote_filter_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote_filter_string("C:\\temp\n"), "\"C:\\\\temp\\n\"");
+
+        let result = CheckResult::new(State::Ok, "OK");
+        let payload = build_payload(&vars("Passive Command", "a \"b\""), &result, &SubmitOptions::default(), &NameTemplates::default(), AddressBy::Filter);
+        assert_eq!(
+            payload["filter"],
+            Value::from("host.name==\"server1\" && service.name==\"Passive Command: a \\\"b\\\"\"")
+        );
+    }
+
+    #[test]
+    fn test_format_check_payload_by_name() {
+        let result = CheckResult::new(State::Ok, "PING OK");
+        let payload = build_payload(&vars("Passive Ping", "router"), &result, &SubmitOptions::default(), &NameTemplates::default(), AddressBy::Name);
+        assert_eq!(payload["service"], Value::from("server1!Passive Ping: router"));
+        assert!(!payload.contains_key("filter"));
+        assert!(!payload.contains_key("host"));
+
+        let options = SubmitOptions { submit_as: SubmitAs::Host, ..Default::default() };
+        let payload = build_payload(&vars("Passive Ping", "nas"), &result, &options, &NameTemplates::default(), AddressBy::Name);
+        assert_eq!(payload["host"], Value::from("nas"));
+        assert!(!payload.contains_key("service"));
+
+        // '!' separates host and service, so it can't appear in the host name
+        let object = resolve_object(&vars("Passive Ping", "a!b"), &options, &NameTemplates::default());
+        let err = format_check_payload(&object, "server1", &result, &options, AddressBy::Name).unwrap_err();
+        assert!(err.contains("host 'a!b'"), "{}", err);
+    }
+
+    #[test]
+    fn test_submission_stats() {
+        let mut stats = SubmissionStats::default();
+        assert_eq!(stats.average_latency(), None);
+
+        stats.record(Duration::from_millis(10), true);
+        stats.record(Duration::from_millis(30), false);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.last_latency, Some(Duration::from_millis(30)));
+        assert_eq!(stats.max_latency, Duration::from_millis(30));
+        assert_eq!(stats.average_latency(), Some(Duration::from_millis(20)));
     }
 }
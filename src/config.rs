@@ -1,120 +1,1137 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use toml::Value;
+use crate::checks::{self, AddressBy, SubmitAs, SubmitOptions};
+use crate::naming::{self, NameTemplates};
+use crate::pings;
+use crate::threshold::Thresholds;
+use crate::endpoints::{EndpointOrder, Endpoints};
+use crate::flapping::FlapConfig;
+use crate::maintenance::Maintenance;
+use crate::sinks::{self, NrdpFormat, Sink, SinkConfig};
+use crate::tls::{self, TlsConfig};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IcingaConfig {
     pub config_path: String,
-    pub api_url: String,
+    /// The Icinga API URLs results are submitted to, with their health
+    pub endpoints: Arc<Endpoints>,
     pub api_user: String,
     pub api_password: String,
     pub debug: bool,
     pub pings: Vec<PingConfig>,
+    pub reports: Vec<ReportConfig>,
     pub sleep_duration: u64,
+    pub http: Option<HttpConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub health: Option<HealthConfig>,
+    pub maintenance: Vec<Maintenance>,
+    /// Global host and service name templates from the icinga section
+    pub names: NameTemplates,
+    /// Whether results address their host or service by filter or by name
+    pub address_by: AddressBy,
+    /// Client shared by all submissions so connections are kept alive
+    pub client: reqwest::blocking::Client,
+    /// The configured sinks including the built-in `icinga` one
+    pub sinks: Vec<SinkConfig>,
+    /// Sinks used by checks that don't name their own
+    pub default_sinks: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PingConfig {
     pub name: String,
     pub host: String,
+    /// Thresholds by performance data label, e.g. `rtavg` or `pl`
+    pub thresholds: BTreeMap<String, Thresholds>,
+    /// Unchanged results are only submitted every this many runs
+    pub refresh_every: u64,
+    /// Re-runs of a check that went from OK to a problem before it is submitted
+    pub retries: u32,
+    /// Seconds between those re-runs
+    pub retry_interval: u64,
+    pub flapping: Option<FlapConfig>,
+    pub submit: SubmitOptions,
 }
 
-pub fn load_config() -> IcingaConfig {
+/// A check that is reported through the control socket and is expected to
+/// arrive at least every `interval` seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportConfig {
+    pub name: String,
+    pub interval: u64,
+    pub missing_status: i32,
+    pub submit: SubmitOptions,
+}
+
+/// Optional local HTTP API, see the `[http]` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpConfig {
+    pub listen: std::net::SocketAddr,
+    pub token: String,
+}
+
+/// Optional Prometheus metrics listener, see the `[metrics]` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsConfig {
+    pub listen: std::net::SocketAddr,
+}
+
+/// Optional self-monitoring check, see the `[health]` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthConfig {
+    pub submit: SubmitOptions,
+    /// More undelivered results than these make the health WARNING or
+    /// CRITICAL, unset they are only reported
+    pub undelivered_warning: Option<u64>,
+    pub undelivered_critical: Option<u64>,
+}
+
+/// Read `service_name` and `host_name` templates from a table.
+fn parse_name_templates(table: &Value, what: &str) -> Result<NameTemplates, String> {
+    let template = |key: &str| -> Result<Option<String>, String> {
+        match table.get(key) {
+            Some(value) => {
+                let template = value.as_str()
+                    .ok_or_else(|| format!("'{}' in {} must be a string", key, what))?;
+                naming::validate(template).map_err(|e| format!("Invalid '{}' in {}: {}", key, what, e))?;
+                Ok(Some(template.to_string()))
+            }
+            None => Ok(None),
+        }
+    };
+    Ok(NameTemplates {
+        service_name: template("service_name")?,
+        host_name: template("host_name")?,
+    })
+}
+
+/// Read the `submit_as`, `ttl`, `check_command` and name template options
+/// of a check. The TTL defaults
+/// to twice the interval the check is expected to be submitted at, so a
+/// result goes stale in Icinga if the daemon stops, `ttl = 0` disables it.
+fn parse_submit_options(
+    check: &Value,
+    what: &str,
+    interval: u64,
+    default_command: Option<Vec<String>>,
+    sinks: &[SinkConfig],
+) -> Result<SubmitOptions, String> {
+    let submit_as = match check.get("submit_as").map(|v| v.as_str()) {
+        Some(Some("service")) | None => SubmitAs::Service,
+        Some(Some("host")) => SubmitAs::Host,
+        Some(_) => return Err(format!("'submit_as' for {} must be \"service\" or \"host\"", what)),
+    };
+
+    let ttl = match check.get("ttl").map(|v| v.as_integer()) {
+        Some(Some(0)) => None,
+        Some(Some(ttl)) if ttl > 0 => Some(ttl as u64),
+        Some(_) => return Err(format!("'ttl' for {} must be a positive number of seconds or 0", what)),
+        None => Some(interval * 2),
+    };
+
+    let check_command = match check.get("check_command") {
+        Some(Value::Array(items)) => Some(items.iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .filter(|items| !items.is_empty())
+            .ok_or_else(|| format!("'check_command' for {} must be a list of strings", what))?),
+        Some(_) => return Err(format!("'check_command' for {} must be a list of strings", what)),
+        None => default_command,
+    };
+
+    let names = parse_name_templates(check, what)?;
+
+    let sinks = match check.get("sinks") {
+        Some(value) => Some(parse_sink_names(value, sinks, &format!("'sinks' for {}", what))?),
+        None => None,
+    };
+
+    Ok(SubmitOptions { submit_as, ttl, check_command, names, sinks })
+}
+
+/// Read a list of sink names, which must all be configured.
+fn parse_sink_names(value: &Value, sinks: &[SinkConfig], what: &str) -> Result<Vec<String>, String> {
+    let names = value.as_array()
+        .and_then(|items| items.iter().map(|item| item.as_str().map(String::from)).collect::<Option<Vec<_>>>())
+        .filter(|names| !names.is_empty())
+        .ok_or_else(|| format!("{} must be a list of sink names", what))?;
+    match names.iter().find(|name| !sinks.iter().any(|sink| sink.name == **name)) {
+        Some(unknown) => Err(format!("Unknown sink '{}' in {}", unknown, what)),
+        None => Ok(names),
+    }
+}
+
+/// Read the `[[sink]]` sections and the default `sinks` of the `[output]`
+/// section, which is just the Icinga API unless configured otherwise.
+fn parse_sinks(config_data: &Value) -> Result<(Vec<SinkConfig>, Vec<String>), String> {
+    let mut sinks = vec![SinkConfig { name: sinks::ICINGA_SINK.to_string(), sink: Sink::Icinga }];
+
+    for sink in config_data.get("sink").and_then(|s| s.as_array()).into_iter().flatten() {
+        let name = sink.get("name")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing 'name' in a sink section")?
+            .to_string();
+        if sinks.iter().any(|existing| existing.name == name) {
+            return Err(format!("Duplicate sink name '{}'", name));
+        }
+        let setting = |key: &str| sink.get(key)
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .ok_or_else(|| format!("Missing '{}' in sink '{}'", key, name));
+        let optional = |key: &str| sink.get(key)
+            .map(|v| v.as_str().map(String::from).ok_or_else(|| format!("'{}' for sink '{}' must be a string", key, name)))
+            .transpose();
+        let kind = match sink.get("type").and_then(|v| v.as_str()) {
+            Some("nrdp") => Sink::Nrdp {
+                url: setting("url")?,
+                token: setting("token")?,
+                format: match sink.get("format").map(|v| v.as_str()) {
+                    Some(Some("xml")) | None => NrdpFormat::Xml,
+                    Some(Some("json")) => NrdpFormat::Json,
+                    Some(_) => return Err(format!("'format' for sink '{}' must be \"xml\" or \"json\"", name)),
+                },
+                client: checks::build_client(&TlsConfig::default())?,
+            },
+            Some("nsca-ng") => Sink::NscaNg {
+                command: optional("command")?.unwrap_or_else(|| "send_nsca".to_string()),
+                server: optional("server")?,
+                port: match sink.get("port").map(|v| v.as_integer()) {
+                    Some(Some(port)) if (1..=65535).contains(&port) => Some(port as u16),
+                    Some(_) => return Err(format!("'port' for sink '{}' must be a port number", name)),
+                    None => None,
+                },
+                config: optional("config")?.map(PathBuf::from),
+            },
+            Some("file") => Sink::File { path: PathBuf::from(setting("path")?) },
+            _ => return Err(format!("'type' for sink '{}' must be \"nrdp\", \"nsca-ng\" or \"file\"", name)),
+        };
+        sinks.push(SinkConfig { name, sink: kind });
+    }
+
+    let default_sinks = match config_data.get("output").and_then(|o| o.get("sinks")) {
+        Some(value) => parse_sink_names(value, &sinks, "'sinks' in the output section")?,
+        None => vec![sinks::ICINGA_SINK.to_string()],
+    };
+
+    Ok((sinks, default_sinks))
+}
+
+/// Where the API password came from, a literal one should not be in a file
+/// others can read.
+#[derive(Debug, PartialEq)]
+enum Password {
+    Literal(String),
+    Secret(String),
+}
+
+const PASSWORD_SOURCES: [&str; 4] = ["api_password", "api_password_file", "api_password_env", "api_password_credential"];
+
+/// Read the API password from the one configured source: the config itself,
+/// a file, an environment variable or a systemd credential
+/// (`LoadCredential=`). Without any, a credential named `api_password` is
+/// used if systemd passed one. `env` looks up environment variables.
+fn parse_password(
+    icinga: &Value,
+    credentials_dir: Option<&Path>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Password>, String> {
+    let configured: Vec<&str> = PASSWORD_SOURCES.into_iter().filter(|key| icinga.get(key).is_some()).collect();
+    if configured.len() > 1 {
+        return Err(format!("Only one of {} can be set in the icinga section", configured.join(", ")));
+    }
+    let value = |key: &str| icinga.get(key)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("'{}' in the icinga section must be a string", key));
+    let read_secret = |path: &Path, what: &str| fs::read_to_string(path)
+        .map(|secret| Password::Secret(secret.trim_end_matches(['\n', '\r']).to_string()))
+        .map_err(|e| format!("Failed to read {} {}: {}", what, path.display(), e));
+
+    match configured.first().copied() {
+        Some("api_password") => Ok(Some(Password::Literal(value("api_password")?.to_string()))),
+        Some("api_password_file") => read_secret(Path::new(value("api_password_file")?), "'api_password_file'").map(Some),
+        Some("api_password_env") => {
+            let name = value("api_password_env")?;
+            env(name)
+                .map(|password| Some(Password::Secret(password)))
+                .ok_or_else(|| format!("Environment variable {} from 'api_password_env' is not set", name))
+        }
+        Some(_) => {
+            let name = value("api_password_credential")?;
+            let dir = credentials_dir
+                .ok_or("'api_password_credential' is set but systemd passed no credentials ($CREDENTIALS_DIRECTORY)")?;
+            read_secret(&dir.join(name), "credential").map(Some)
+        }
+        None => match credentials_dir.map(|dir| dir.join("api_password")).filter(|path| path.exists()) {
+            Some(path) => read_secret(&path, "credential").map(Some),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Warn when the config holds a literal password but group or others can
+/// read it, as is common for files in /etc.
+fn warn_if_readable(config_path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = fs::metadata(config_path) {
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            eprintln!(
+                "Warning: {} contains api_password but is readable by group or others (mode {:o}), \
+                 restrict it with chmod 600 or use api_password_file, api_password_env or a systemd credential",
+                config_path, mode
+            );
+        }
+    }
+}
+
+/// Read `api_url`, a single URL or a list tried in order (or round-robin
+/// with `endpoint_order`), and the back-off for failing endpoints.
+fn parse_endpoints(icinga: &Value) -> Result<Endpoints, String> {
+    let urls = match icinga.get("api_url") {
+        Some(Value::String(url)) => vec![url.clone()],
+        Some(Value::Array(items)) => items.iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .filter(|urls| !urls.is_empty())
+            .ok_or("'api_url' in the icinga section must be a URL or a list of URLs")?,
+        Some(_) => return Err("'api_url' in the icinga section must be a URL or a list of URLs".to_string()),
+        // Only needed when the icinga sink is used, checked once the sinks are known
+        None => Vec::new(),
+    };
+
+    let order = match icinga.get("endpoint_order").map(|v| v.as_str()) {
+        Some(Some("failover")) | None => EndpointOrder::Failover,
+        Some(Some("round-robin")) => EndpointOrder::RoundRobin,
+        Some(_) => return Err("'endpoint_order' in the icinga section must be \"failover\" or \"round-robin\"".to_string()),
+    };
+
+    let backoff = match icinga.get("endpoint_backoff").map(|v| v.as_integer()) {
+        Some(Some(secs)) if secs > 0 => secs as u64,
+        Some(_) => return Err("'endpoint_backoff' in the icinga section must be a positive number of seconds".to_string()),
+        None => 30,
+    };
+
+    Ok(Endpoints::new(urls, order, Duration::from_secs(backoff)))
+}
+
+/// Read the TLS options of the icinga section.
+fn parse_tls(icinga: &Value) -> Result<TlsConfig, String> {
+    let path = |key: &str| match icinga.get(key) {
+        Some(Value::String(path)) => Ok(Some(path.clone())),
+        Some(_) => Err(format!("'{}' in the icinga section must be a path", key)),
+        None => Ok(None),
+    };
+
+    let client_cert = match (path("client_cert")?, path("client_key")?) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => return Err("'client_cert' and 'client_key' in the icinga section must be set together".to_string()),
+    };
+
+    let fingerprints = match icinga.get("fingerprint") {
+        Some(Value::String(fingerprint)) => vec![tls::parse_fingerprint(fingerprint)?],
+        Some(Value::Array(items)) => items.iter()
+            .map(|item| item.as_str()
+                .ok_or("'fingerprint' in the icinga section must be a string or a list of strings".to_string())
+                .and_then(tls::parse_fingerprint))
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err("'fingerprint' in the icinga section must be a string or a list of strings".to_string()),
+        None => Vec::new(),
+    };
+
+    let insecure_skip_verify = match icinga.get("insecure_skip_verify") {
+        Some(Value::Boolean(skip)) => *skip,
+        Some(_) => return Err("'insecure_skip_verify' in the icinga section must be true or false".to_string()),
+        None => false,
+    };
+
+    Ok(TlsConfig { ca_file: path("ca_file")?, client_cert, fingerprints, insecure_skip_verify })
+}
+
+pub fn find_config_path() -> Option<String> {
     let mut config_paths = vec!["config.toml".to_string()];
-    
+
     if let Some(home) = std::env::var_os("HOME") {
         let home = home.to_string_lossy();
         config_paths.push(format!("{}/.icinga_passive_checks.toml", home));
         config_paths.push(format!("{}/.config/icinga_passive_checks.toml", home));
     }
-    
+
     config_paths.push("/etc/icinga_passive_checks.toml".to_string());
 
-    let config_path = config_paths.into_iter()
+    config_paths.into_iter()
         .find(|path| std::path::Path::new(path).exists())
-        .unwrap_or_else(|| {
-            println!("Error: No config file found in standard locations");
-            std::process::exit(1);
-        });
+}
+
+/// Load the config or exit the process, used at startup where there is
+/// nothing sensible to fall back to.
+pub fn load_config() -> IcingaConfig {
+    let config_path = find_config_path().unwrap_or_else(|| {
+        println!("Error: No config file found in standard locations");
+        std::process::exit(1);
+    });
+
+    read_config(&config_path).unwrap_or_else(|e| {
+        println!("Error: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Read and validate the config file at `config_path` without exiting on
+/// errors, so a running daemon can keep its old config if this fails.
+pub fn read_config(config_path: &str) -> Result<IcingaConfig, String> {
+    let config_content = fs::read_to_string(config_path)
+        .map_err(|e| format!("Failed to read config file {}: {}", config_path, e))?;
+    parse_config(config_path, &config_content)
+}
+
+pub fn parse_config(config_path: &str, config_content: &str) -> Result<IcingaConfig, String> {
+    let config_data: Value = toml::from_str(config_content)
+        .map_err(|e| format!("Failed to parse config file: {}", e))?;
+
+    let icinga = config_data.get("icinga")
+        .and_then(|v| v.as_table())
+        .ok_or("Missing 'icinga' section in config file")?;
+
+    let endpoints = parse_endpoints(&config_data["icinga"])?;
 
-    let config_content = fs::read_to_string(&config_path)
-        .expect(&format!("Failed to read config file: {}", config_path));
-    let config_data: Value = toml::from_str(&config_content).expect("Failed to parse config file");
+    let tls = parse_tls(&config_data["icinga"])?;
 
-    // Rest of the function remains the same
-    let icinga = match config_data["icinga"].as_table() {
-        Some(table) => table,
-        None => {
-            println!("Error: Missing 'icinga' section in config file");
-            std::process::exit(1);
+    let api_user = icinga.get("api_user").and_then(|v| v.as_str()).map(String::from);
+    let credentials_dir = std::env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+    let env = |name: &str| std::env::var(name).ok();
+    let api_password = match parse_password(&config_data["icinga"], credentials_dir.as_deref(), env)? {
+        Some(Password::Literal(password)) => {
+            warn_if_readable(config_path);
+            Some(password)
         }
+        Some(Password::Secret(password)) => Some(password),
+        None => None,
     };
 
-    let api_url = match icinga.get("api_url").and_then(|v| v.as_str()) {
-        Some(url) => url.to_string(),
-        None => {
-            println!("Error: Missing 'api_url' in the icinga section");
-            std::process::exit(1);
-        }
+    let (sinks, default_sinks) = parse_sinks(&config_data)?;
+
+    let names = parse_name_templates(&config_data["icinga"], "the icinga section")?;
+
+    let address_by = match icinga.get("address_by").map(|v| v.as_str()) {
+        Some(Some("filter")) | None => AddressBy::Filter,
+        Some(Some("name")) => AddressBy::Name,
+        Some(_) => return Err("'address_by' in the icinga section must be \"filter\" or \"name\"".to_string()),
     };
 
-    let api_user = match icinga.get("api_user").and_then(|v| v.as_str()) {
-        Some(user) => user.to_string(),
-        None => {
-            println!("Error: Missing 'api_user' in the icinga section");
-            std::process::exit(1);
-        }
+    let debug = config_data.get("command")
+        .and_then(|c| c.get("debug").and_then(|v| v.as_bool()))
+        .unwrap_or_default();
+
+    let sleep_duration = match config_data.get("daemon")
+        .and_then(|d| d.as_table())
+        .and_then(|d| d.get("sleep_duration"))
+        .and_then(|v| v.as_integer()) {
+        Some(duration) if duration > 0 => duration as u64,
+        Some(_) => return Err("'sleep_duration' in the daemon section must be positive".to_string()),
+        None => 60,
     };
 
-    let api_password = match icinga.get("api_password").and_then(|v| v.as_str()) {
-        Some(password) => password.to_string(),
-        None => {
-            println!("Error: Missing 'api_password' in the icinga section");
-            std::process::exit(1);
+    let parse_refresh_every = |table: Option<&Value>, what: &str| -> Result<Option<u64>, String> {
+        match table.and_then(|t| t.get("refresh_every")).map(|v| v.as_integer()) {
+            Some(Some(n)) if n > 0 => Ok(Some(n as u64)),
+            Some(_) => Err(format!("'refresh_every' in {} must be a positive number of runs", what)),
+            None => Ok(None),
         }
     };
+    let default_refresh_every = parse_refresh_every(config_data.get("daemon"), "the daemon section")?.unwrap_or(1);
 
-    let debug = match config_data.get("command").and_then(|c| c.get("debug").and_then(|v| v.as_bool())) {
-        Some(debug) => debug,
-        None => false,
-    };    
-
-    let pings = match config_data.get("ping").and_then(|p| p.as_array()) {
-        Some(ping_array) => ping_array.iter().map(|ping| {
-            PingConfig {
-                name: match ping.get("name").and_then(|v| v.as_str()) {
-                    Some(name) => name.to_string(),
-                    None => {
-                        println!("Error: Missing 'name' in a ping section");
-                        std::process::exit(1);
-                    }
-                },
-                host: match ping.get("host").and_then(|v| v.as_str()) {
-                    Some(host) => host.to_string(),
-                    None => {
-                        println!("Error: Missing 'host' in a ping section");
-                        std::process::exit(1);
+    // Pings run one after another, with many of them a round takes longer
+    // than sleep_duration and the TTLs have to allow for that
+    let ping_count = config_data.get("ping").and_then(|p| p.as_array()).map_or(0, |p| p.len());
+    let cycle = sleep_duration.max(ping_count as u64 * pings::EXPECTED_DURATION);
+
+    let mut pings = Vec::new();
+    if let Some(ping_array) = config_data.get("ping").and_then(|p| p.as_array()) {
+        for ping in ping_array {
+            let name = ping.get("name")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing 'name' in a ping section")?
+                .to_string();
+            let mut thresholds = BTreeMap::new();
+            if let Some(table) = ping.get("thresholds") {
+                let table = table.as_table()
+                    .ok_or_else(|| format!("'thresholds' for ping '{}' must be a table", name))?;
+                for (label, value) in table {
+                    if !pings::PERFDATA_LABELS.contains(&label.as_str()) {
+                        return Err(format!(
+                            "Unknown threshold '{}' for ping '{}', expected one of {}",
+                            label, name, pings::PERFDATA_LABELS.join(", ")));
                     }
-                },
+                    let value = Thresholds::from_toml(value)
+                        .map_err(|e| format!("Invalid threshold '{}' for ping '{}': {}", label, name, e))?;
+                    thresholds.insert(label.clone(), value);
+                }
             }
-        }).collect(),
-        None => Vec::new(),
+            let host = ping.get("host")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing 'host' in a ping section")?
+                .to_string();
+            let what = format!("ping '{}'", name);
+            let refresh_every = parse_refresh_every(Some(ping), &what)?.unwrap_or(default_refresh_every);
+            // The TTL has to outlast the time between two submissions of an
+            // unchanged result
+            let refresh_period = cycle * refresh_every;
+            let retries = match ping.get("retries").map(|v| v.as_integer()) {
+                Some(Some(retries)) if (0..=100).contains(&retries) => retries as u32,
+                Some(_) => return Err(format!("'retries' for {} must be a number from 0 to 100", what)),
+                None => 0,
+            };
+            let retry_interval = match ping.get("retry_interval").map(|v| v.as_integer()) {
+                Some(Some(interval)) if interval > 0 => interval as u64,
+                Some(_) => return Err(format!("'retry_interval' for {} must be a positive number of seconds", what)),
+                None => sleep_duration.min(60),
+            };
+            let mut submit = parse_submit_options(
+                ping,
+                &what,
+                refresh_period,
+                Some(pings::command_line(&host)),
+                &sinks,
+            )?;
+            // Nothing is submitted while retrying, the last result must not
+            // go stale in the meantime
+            let retry_period = retries as u64 * retry_interval;
+            if ping.get("ttl").is_none() {
+                submit.ttl = submit.ttl.map(|ttl| ttl + retry_period);
+            }
+            if let Some(ttl) = submit.ttl {
+                if retry_period > ttl {
+                    return Err(format!(
+                        "'retries' times 'retry_interval' for {} must not be longer than its TTL of {} seconds",
+                        what, ttl));
+                }
+                if refresh_every > 1 && ttl <= refresh_period {
+                    return Err(format!(
+                        "'ttl' for {} must be longer than its refresh period of {} seconds",
+                        what, refresh_period));
+                }
+            }
+            let flapping = match ping.get("flapping") {
+                Some(value) => Some(FlapConfig::from_toml(value)
+                    .map_err(|e| format!("Invalid 'flapping' for {}: {}", what, e))?),
+                None => None,
+            };
+            pings.push(PingConfig {
+                name, host, thresholds, refresh_every, retries, retry_interval, flapping, submit,
+            });
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for ping in &pings {
+        if !seen.insert(&ping.name) {
+            return Err(format!("Duplicate ping name '{}'", ping.name));
+        }
+    }
+
+    let mut reports = Vec::new();
+    if let Some(report_array) = config_data.get("report").and_then(|p| p.as_array()) {
+        for report in report_array {
+            let name = report.get("name")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing 'name' in a report section")?
+                .to_string();
+            let interval = match report.get("interval").and_then(|v| v.as_integer()) {
+                Some(interval) if interval > 0 => interval as u64,
+                Some(_) => return Err(format!("'interval' for report '{}' must be positive", name)),
+                None => return Err(format!("Missing 'interval' in report '{}'", name)),
+            };
+            let missing_status = match report.get("missing_status").and_then(|v| v.as_integer()) {
+                Some(status @ 1..=3) => status as i32,
+                Some(_) => return Err(format!("'missing_status' for report '{}' must be 1, 2 or 3", name)),
+                None => 2,
+            };
+            let submit = parse_submit_options(report, &format!("report '{}'", name), interval, None, &sinks)?;
+            reports.push(ReportConfig { name, interval, missing_status, submit });
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for report in &reports {
+        if !seen.insert(&report.name) {
+            return Err(format!("Duplicate report name '{}'", report.name));
+        }
+    }
+
+    let health = match config_data.get("health") {
+        Some(health) if health.is_table() => {
+            let submit = parse_submit_options(health, "the health section", cycle, None, &sinks)?;
+            let threshold = |key: &str| match health.get(key).map(|v| v.as_integer()) {
+                Some(Some(count)) if count >= 0 => Ok(Some(count as u64)),
+                Some(_) => Err(format!("'{}' in the health section must be a number of results", key)),
+                None => Ok(None),
+            };
+            let undelivered_warning = threshold("undelivered_warning")?;
+            let undelivered_critical = threshold("undelivered_critical")?;
+            if let (Some(warning), Some(critical)) = (undelivered_warning, undelivered_critical) {
+                if warning > critical {
+                    return Err("'undelivered_warning' in the health section can't be above 'undelivered_critical'".to_string());
+                }
+            }
+            Some(HealthConfig { submit, undelivered_warning, undelivered_critical })
+        }
+        Some(_) => return Err("'health' must be a table".to_string()),
+        None => None,
     };
 
-    let sleep_duration = match config_data.get("daemon")
-        .and_then(|d| d.as_table())
-        .and_then(|d| d.get("sleep_duration"))
-        .and_then(|v| v.as_integer()) {
-        Some(duration) => duration as u64,
-        None => 60,
+    let mut maintenance = Vec::new();
+    if let Some(maintenance_array) = config_data.get("maintenance").and_then(|m| m.as_array()) {
+        for window in maintenance_array {
+            maintenance.push(Maintenance::from_toml(window)?);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for window in &maintenance {
+        if !seen.insert(&window.name) {
+            return Err(format!("Duplicate maintenance name '{}'", window.name));
+        }
+    }
+
+    // The API settings are only required when results go to Icinga
+    let uses_icinga = default_sinks.iter()
+        .chain(pings.iter().filter_map(|ping| ping.submit.sinks.as_ref()).flatten())
+        .chain(reports.iter().filter_map(|report| report.submit.sinks.as_ref()).flatten())
+        .chain(health.iter().filter_map(|health| health.submit.sinks.as_ref()).flatten())
+        .any(|name| name == sinks::ICINGA_SINK);
+    if uses_icinga {
+        if endpoints.urls().is_empty() {
+            return Err("Missing 'api_url' in the icinga section".to_string());
+        }
+        // With a client certificate Icinga can authenticate the API user by
+        // certificate alone
+        if tls.client_cert.is_none() {
+            if api_user.is_none() {
+                return Err("Missing 'api_user' in the icinga section".to_string());
+            }
+            if api_password.is_none() {
+                return Err("Missing 'api_password' in the icinga section".to_string());
+            }
+        }
+    }
+
+    let http = match config_data.get("http").and_then(|h| h.as_table()) {
+        Some(http) => {
+            let listen = http.get("listen")
+                .and_then(|v| v.as_str())
+                .unwrap_or("127.0.0.1:8765")
+                .parse()
+                .map_err(|e| format!("Invalid 'listen' address in the http section: {}", e))?;
+            let token = http.get("token")
+                .and_then(|v| v.as_str())
+                .filter(|token| !token.is_empty())
+                .ok_or("Missing 'token' in the http section")?
+                .to_string();
+            Some(HttpConfig { listen, token })
+        }
+        None => None,
     };
 
-    IcingaConfig {
-        config_path,
-        api_url,
-        api_user,
-        api_password,
+    let metrics = match config_data.get("metrics").and_then(|m| m.as_table()) {
+        Some(metrics) => {
+            let listen = metrics.get("listen")
+                .and_then(|v| v.as_str())
+                .unwrap_or("127.0.0.1:9469")
+                .parse()
+                .map_err(|e| format!("Invalid 'listen' address in the metrics section: {}", e))?;
+            Some(MetricsConfig { listen })
+        }
+        None => None,
+    };
+
+    Ok(IcingaConfig {
+        config_path: config_path.to_string(),
+        endpoints: Arc::new(endpoints),
+        api_user: api_user.unwrap_or_default(),
+        api_password: api_password.unwrap_or_default(),
         debug,
         pings,
+        reports,
         sleep_duration,
+        http,
+        metrics,
+        health,
+        maintenance,
+        names,
+        address_by,
+        client: checks::build_client(&tls)?,
+        sinks,
+        default_sinks,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+[icinga]
+api_url = "https://icinga/v1/actions/process-check-result"
+api_user = "user"
+api_password = "secret"
+"#;
+
+    /// The minimal config followed by `extra`, for the other modules' tests.
+    pub(crate) fn minimal(extra: &str) -> IcingaConfig {
+        parse_config("test.toml", &format!("{}{}", MINIMAL, extra)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_minimal_config() {
+        let config = parse_config("test.toml", MINIMAL).unwrap();
+        assert_eq!(config.config_path, "test.toml");
+        assert_eq!(config.api_user, "user");
+        assert_eq!(config.sleep_duration, 60);
+        assert!(config.pings.is_empty());
+        assert!(!config.debug);
+    }
+
+    #[test]
+    fn test_parse_pings() {
+        let content = format!("{}\n[[ping]]\nname = \"router\"\nhost = \"192.168.1.1\"\n", MINIMAL);
+        let config = parse_config("test.toml", &content).unwrap();
+        assert_eq!(config.pings, vec![PingConfig {
+            name: "router".to_string(),
+            host: "192.168.1.1".to_string(),
+            thresholds: BTreeMap::new(),
+            refresh_every: 1,
+            retries: 0,
+            retry_interval: 60,
+            flapping: None,
+            submit: SubmitOptions {
+                submit_as: SubmitAs::Service,
+                ttl: Some(120),
+                check_command: Some(pings::command_line("192.168.1.1")),
+                names: NameTemplates::default(),
+                sinks: None,
+            },
+        }]);
+    }
+
+    #[test]
+    fn test_parse_refresh_every() {
+        let content = format!(
+            "{}\n[daemon]\nsleep_duration = 300\nrefresh_every = 4\n[[ping]]\nname = \"a\"\nhost = \"h1\"\n[[ping]]\nname = \"b\"\nhost = \"h2\"\nrefresh_every = 1\n",
+            MINIMAL
+        );
+        let config = parse_config("test.toml", &content).unwrap();
+        assert_eq!(config.pings[0].refresh_every, 4);
+        assert_eq!(config.pings[0].submit.ttl, Some(2400));
+        assert_eq!(config.pings[1].refresh_every, 1);
+        assert_eq!(config.pings[1].submit.ttl, Some(600));
+
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nrefresh_every = 5\nttl = 300\n", MINIMAL);
+        let err = parse_config("test.toml", &content).unwrap_err();
+        assert_eq!(err, "'ttl' for ping 'a' must be longer than its refresh period of 300 seconds");
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nrefresh_every = 0\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'refresh_every' in ping 'a'"));
+    }
+
+    #[test]
+    fn test_parse_retries() {
+        let content = format!(
+            "{}\n[daemon]\nsleep_duration = 30\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nretries = 3\n[[ping]]\nname = \"b\"\nhost = \"h2\"\nretries = 2\nretry_interval = 5\n",
+            MINIMAL
+        );
+        let config = parse_config("test.toml", &content).unwrap();
+        assert_eq!((config.pings[0].retries, config.pings[0].retry_interval), (3, 30));
+        assert_eq!((config.pings[1].retries, config.pings[1].retry_interval), (2, 5));
+
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nretries = -1\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'retries' for ping 'a'"));
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nretry_interval = 0\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'retry_interval' for ping 'a'"));
+
+        // The default TTL allows for the retries, an explicit one has to
+        assert_eq!(config.pings[0].submit.ttl, Some(60 + 90));
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nretries = 100\nttl = 3600\n", MINIMAL);
+        assert_eq!(
+            parse_config("test.toml", &content).unwrap_err(),
+            "'retries' times 'retry_interval' for ping 'a' must not be longer than its TTL of 3600 seconds"
+        );
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nretries = 100\nttl = 0\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).is_ok());
+    }
+
+    #[test]
+    fn test_parse_flapping() {
+        let content = format!(
+            "{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nflapping = {{ window = 10, dampen = true }}\n",
+            MINIMAL
+        );
+        let flapping = parse_config("test.toml", &content).unwrap().pings[0].flapping.clone().unwrap();
+        assert_eq!((flapping.window, flapping.dampen), (10, true));
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nflapping = {{ high = 150 }}\n", MINIMAL);
+        assert_eq!(
+            parse_config("test.toml", &content).unwrap_err(),
+            "Invalid 'flapping' for ping 'a': 'high' must be between 0 and 100"
+        );
+    }
+
+    #[test]
+    fn test_parse_maintenance() {
+        let content = format!(
+            "{}\n[[maintenance]]\nname = \"backup\"\nstart = \"01:00\"\nend = \"03:00\"\nchecks = [\"nas\"]\naction = \"downtime\"\n",
+            MINIMAL
+        );
+        let maintenance = parse_config("test.toml", &content).unwrap().maintenance;
+        assert_eq!(maintenance.len(), 1);
+        assert_eq!(maintenance[0].checks, vec!["nas"]);
+
+        let content = format!("{}\n[[maintenance]]\nname = \"backup\"\nstart = \"01:00\"\n", MINIMAL);
+        assert_eq!(parse_config("test.toml", &content).unwrap_err(), "Invalid maintenance 'backup': missing 'end'");
+    }
+
+    #[test]
+    fn test_parse_submit_options() {
+        let content = format!(
+            "{}\n[daemon]\nsleep_duration = 300\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nttl = 900\ncheck_command = [\"check_ping\", \"-H\", \"h1\"]\n[[ping]]\nname = \"b\"\nhost = \"h2\"\nttl = 0\n",
+            MINIMAL
+        );
+        let config = parse_config("test.toml", &content).unwrap();
+        assert_eq!(config.pings[0].submit.ttl, Some(900));
+        assert_eq!(config.pings[0].submit.check_command, Some(vec![
+            "check_ping".to_string(), "-H".to_string(), "h1".to_string(),
+        ]));
+        assert_eq!(config.pings[1].submit.ttl, None);
+
+        let content = format!("{}\n[[ping]]\nname = \"nas\"\nhost = \"h1\"\nsubmit_as = \"host\"\n", MINIMAL);
+        assert_eq!(parse_config("test.toml", &content).unwrap().pings[0].submit.submit_as, SubmitAs::Host);
+        let content = format!("{}\n[[ping]]\nname = \"nas\"\nhost = \"h1\"\nsubmit_as = \"hostgroup\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'submit_as' for ping 'nas'"));
+
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\nttl = -5\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'ttl' for ping 'a'"));
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\ncheck_command = \"ping\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'check_command' for ping 'a'"));
+    }
+
+    #[test]
+    fn test_default_ttl_covers_ping_cycle() {
+        let ping = |i: usize| format!("[[ping]]\nname = \"p{}\"\nhost = \"10.0.0.{}\"\n", i, i);
+        let content = format!("{}\n[daemon]\nsleep_duration = 30\n{}", MINIMAL, ping(1));
+        assert_eq!(parse_config("test.toml", &content).unwrap().pings[0].submit.ttl, Some(60));
+
+        // 12 pings take about 120 seconds per round, longer than sleep_duration
+        let pings: String = (1..=12).map(ping).collect();
+        let content = format!("{}\n[daemon]\nsleep_duration = 30\n{}[health]\n", MINIMAL, pings);
+        let config = parse_config("test.toml", &content).unwrap();
+        assert_eq!(config.pings[0].submit.ttl, Some(240));
+        assert_eq!(config.health.unwrap().submit.ttl, Some(240));
+    }
+
+    #[test]
+    fn test_parse_ping_thresholds() {
+        let content = format!(
+            "{}\n[[ping]]\nname = \"wifi\"\nhost = \"10.0.0.9\"\n[ping.thresholds]\npl = {{ warn = 10, crit = \"30\" }}\nrtavg = {{ crit = \"@0:0.1\" }}\n",
+            MINIMAL
+        );
+        let config = parse_config("test.toml", &content).unwrap();
+        let thresholds = &config.pings[0].thresholds;
+        assert_eq!(thresholds["pl"], Thresholds::new(Some("10"), Some("30")).unwrap());
+        assert_eq!(thresholds["rtavg"], Thresholds::new(None, Some("@0:0.1")).unwrap());
+    }
+
+    #[test]
+    fn test_parse_ping_unknown_threshold() {
+        let content = format!(
+            "{}\n[[ping]]\nname = \"wifi\"\nhost = \"10.0.0.9\"\n[ping.thresholds]\nrta = {{ warn = 10 }}\n",
+            MINIMAL
+        );
+        let err = parse_config("test.toml", &content).unwrap_err();
+        assert!(err.contains("Unknown threshold 'rta' for ping 'wifi'"));
+    }
+
+    #[test]
+    fn test_missing_icinga_section() {
+        let err = parse_config("test.toml", "[daemon]\nsleep_duration = 10\n").unwrap_err();
+        assert!(err.contains("'icinga' section"));
+    }
+
+    #[test]
+    fn test_duplicate_ping_names() {
+        let content = format!(
+            "{}\n[[ping]]\nname = \"a\"\nhost = \"h1\"\n[[ping]]\nname = \"a\"\nhost = \"h2\"\n",
+            MINIMAL
+        );
+        let err = parse_config("test.toml", &content).unwrap_err();
+        assert!(err.contains("Duplicate ping name 'a'"));
+    }
+
+    #[test]
+    fn test_parse_reports() {
+        let content = format!(
+            "{}\n[[report]]\nname = \"zpool\"\ninterval = 3600\n[[report]]\nname = \"backup\"\ninterval = 86400\nmissing_status = 3\n",
+            MINIMAL
+        );
+        let config = parse_config("test.toml", &content).unwrap();
+        assert_eq!(config.reports, vec![
+            ReportConfig {
+                name: "zpool".to_string(),
+                interval: 3600,
+                missing_status: 2,
+                submit: SubmitOptions { ttl: Some(7200), ..Default::default() },
+            },
+            ReportConfig {
+                name: "backup".to_string(),
+                interval: 86400,
+                missing_status: 3,
+                submit: SubmitOptions { ttl: Some(172800), ..Default::default() },
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_report_invalid_missing_status() {
+        let content = format!("{}\n[[report]]\nname = \"zpool\"\ninterval = 60\nmissing_status = 0\n", MINIMAL);
+        let err = parse_config("test.toml", &content).unwrap_err();
+        assert!(err.contains("'missing_status'"));
+    }
+
+    #[test]
+    fn test_parse_http() {
+        let content = format!("{}\n[http]\nlisten = \"127.0.0.1:8080\"\ntoken = \"abc\"\n", MINIMAL);
+        let http = parse_config("test.toml", &content).unwrap().http.unwrap();
+        assert_eq!(http.listen.to_string(), "127.0.0.1:8080");
+        assert_eq!(http.token, "abc");
+
+        let content = format!("{}\n[http]\nlisten = \"127.0.0.1:8080\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'token'"));
+    }
+
+    #[test]
+    fn test_parse_metrics() {
+        assert_eq!(parse_config("test.toml", MINIMAL).unwrap().metrics, None);
+        let content = format!("{}\n[metrics]\n", MINIMAL);
+        let metrics = parse_config("test.toml", &content).unwrap().metrics.unwrap();
+        assert_eq!(metrics.listen.to_string(), "127.0.0.1:9469");
+        let content = format!("{}\n[metrics]\nlisten = \"nowhere\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("metrics section"));
+    }
+
+    #[test]
+    fn test_parse_health() {
+        assert_eq!(parse_config("test.toml", MINIMAL).unwrap().health, None);
+        let content = format!("{}\n[health]\n", MINIMAL);
+        let health = parse_config("test.toml", &content).unwrap().health.unwrap();
+        assert_eq!(health.submit.ttl, Some(120));
+        let content = format!("{}\n[health]\nservice_name = \"agent-{{name}}\"\nttl = 0\n", MINIMAL);
+        let health = parse_config("test.toml", &content).unwrap().health.unwrap();
+        assert_eq!(health.submit.ttl, None);
+        assert_eq!(health.submit.names.service_name.as_deref(), Some("agent-{name}"));
+        assert_eq!((health.undelivered_warning, health.undelivered_critical), (None, None));
+
+        let content = format!("{}\n[health]\nundelivered_warning = 10\nundelivered_critical = 100\n", MINIMAL);
+        let health = parse_config("test.toml", &content).unwrap().health.unwrap();
+        assert_eq!((health.undelivered_warning, health.undelivered_critical), (Some(10), Some(100)));
+        let content = format!("{}\n[health]\nundelivered_warning = 100\nundelivered_critical = 10\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("can't be above"));
+        let content = format!("{}\n[health]\nundelivered_warning = -1\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).is_err());
+    }
+
+    #[test]
+    fn test_parse_name_templates() {
+        let content = r#"
+[icinga]
+api_url = "https://icinga/v1/actions/process-check-result"
+api_user = "user"
+api_password = "secret"
+host_name = "{fqdn}"
+
+[[ping]]
+name = "router"
+host = "192.168.1.1"
+service_name = "ping-{name}"
+"#;
+        let config = parse_config("test.toml", content).unwrap();
+        assert_eq!(config.names.host_name.as_deref(), Some("{fqdn}"));
+        assert_eq!(config.names.service_name, None);
+        assert_eq!(config.pings[0].submit.names.service_name.as_deref(), Some("ping-{name}"));
+
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h\"\nservice_name = \"ping-{{nam}}\"\n", MINIMAL);
+        let err = parse_config("test.toml", &content).unwrap_err();
+        assert!(err.contains("Invalid 'service_name' in ping 'a'"));
+    }
+
+    #[test]
+    fn test_parse_address_by() {
+        assert_eq!(parse_config("test.toml", MINIMAL).unwrap().address_by, AddressBy::Filter);
+
+        let content = MINIMAL.replace("[icinga]", "[icinga]\naddress_by = \"name\"");
+        assert_eq!(parse_config("test.toml", &content).unwrap().address_by, AddressBy::Name);
+
+        let content = MINIMAL.replace("[icinga]", "[icinga]\naddress_by = \"id\"");
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'address_by'"));
+    }
+
+    fn password(extra: &str, credentials_dir: Option<&Path>) -> Result<Option<Password>, String> {
+        let icinga: Value = toml::from_str(extra).unwrap();
+        // A stand-in environment, the real one is shared by the parallel tests
+        let env = |name: &str| (name == "ICINGA_PASSWORD").then(|| "from env".to_string());
+        parse_password(&icinga, credentials_dir, env)
+    }
+
+    #[test]
+    fn test_parse_password_sources() {
+        let dir = std::env::temp_dir().join(format!("icinga_passive_checks_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("password"), "from file\n").unwrap();
+        fs::write(dir.join("api_password"), "from systemd").unwrap();
+
+        assert_eq!(password("api_password = \"literal\"", None).unwrap(), Some(Password::Literal("literal".to_string())));
+        let file = format!("api_password_file = \"{}\"", dir.join("password").display());
+        assert_eq!(password(&file, None).unwrap(), Some(Password::Secret("from file".to_string())));
+        assert_eq!(
+            password("api_password_credential = \"password\"", Some(&dir)).unwrap(),
+            Some(Password::Secret("from file".to_string()))
+        );
+        // Without a configured source systemd's api_password credential is used
+        assert_eq!(password("", Some(&dir)).unwrap(), Some(Password::Secret("from systemd".to_string())));
+        assert_eq!(password("", None).unwrap(), None);
+
+        assert_eq!(
+            password("api_password_env = \"ICINGA_PASSWORD\"", None).unwrap(),
+            Some(Password::Secret("from env".to_string()))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_password_errors() {
+        let err = password("api_password = \"a\"\napi_password_env = \"B\"", None).unwrap_err();
+        assert!(err.contains("Only one of api_password, api_password_env"), "{}", err);
+        assert!(password("api_password_env = \"ICINGA_PASSIVE_CHECKS_UNSET\"", None).unwrap_err().contains("is not set"));
+        assert!(password("api_password_file = \"/nonexistent\"", None).unwrap_err().contains("'api_password_file'"));
+        assert!(password("api_password_credential = \"pw\"", None).unwrap_err().contains("$CREDENTIALS_DIRECTORY"));
+    }
+
+    #[test]
+    fn test_parse_sinks() {
+        let content = r#"
+[icinga]
+
+[output]
+sinks = ["site2", "log"]
+
+[[sink]]
+name = "site2"
+type = "nrdp"
+url = "https://nagios.site2/nrdp/"
+token = "s3cret"
+format = "json"
+
+[[sink]]
+name = "log"
+type = "file"
+path = "/var/log/icinga_passive_checks.jsonl"
+
+[[ping]]
+name = "router"
+host = "192.168.1.1"
+sinks = ["log"]
+"#;
+        // Without the icinga sink the API settings aren't needed
+        let config = parse_config("test.toml", content).unwrap();
+        assert_eq!(config.default_sinks, vec!["site2", "log"]);
+        assert_eq!(config.sinks.len(), 3);
+        match &config.sinks[1].sink {
+            Sink::Nrdp { url, token, format, .. } => {
+                assert_eq!(url, "https://nagios.site2/nrdp/");
+                assert_eq!(token, "s3cret");
+                assert_eq!(*format, NrdpFormat::Json);
+            }
+            sink => panic!("expected an NRDP sink, got {:?}", sink),
+        }
+        assert_eq!(config.pings[0].submit.sinks, Some(vec!["log".to_string()]));
+        assert_eq!(parse_config("test.toml", MINIMAL).unwrap().default_sinks, vec!["icinga"]);
+
+        let content = content.replace("sinks = [\"log\"]", "sinks = [\"icinga\"]");
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("Missing 'api_url'"));
+        let content = format!("{}\n[[ping]]\nname = \"a\"\nhost = \"h\"\nsinks = [\"nagios\"]\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("Unknown sink 'nagios' in 'sinks' for ping 'a'"));
+        let content = format!("{}\n[[sink]]\nname = \"icinga\"\ntype = \"file\"\npath = \"x\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("Duplicate sink name 'icinga'"));
+    }
+
+    #[test]
+    fn test_parse_nsca_ng_sink() {
+        let content = format!(
+            "{}\n[[sink]]\nname = \"site3\"\ntype = \"nsca-ng\"\nserver = \"nagios.site3\"\nport = 5668\n",
+            MINIMAL
+        );
+        match &parse_config("test.toml", &content).unwrap().sinks[1].sink {
+            Sink::NscaNg { command, server, port, config } => {
+                assert_eq!(command, "send_nsca");
+                assert_eq!(server.as_deref(), Some("nagios.site3"));
+                assert_eq!(*port, Some(5668));
+                assert_eq!(*config, None);
+            }
+            sink => panic!("expected an NSCA-ng sink, got {:?}", sink),
+        }
+        let content = format!("{}\n[[sink]]\nname = \"site3\"\ntype = \"nsca-ng\"\nport = 70000\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'port' for sink 'site3'"));
+        let content = format!("{}\n[[sink]]\nname = \"site3\"\ntype = \"nsca-ng\"\nconfig = 1\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'config' for sink 'site3' must be a string"));
+    }
+
+    #[test]
+    fn test_parse_endpoints() {
+        let config = parse_config("test.toml", MINIMAL).unwrap();
+        assert_eq!(config.endpoints.urls(), ["https://icinga/v1/actions/process-check-result"]);
+
+        let content = r#"
+[icinga]
+api_url = ["https://master1:5665/v1/actions/process-check-result", "https://master2:5665/v1/actions/process-check-result"]
+endpoint_order = "round-robin"
+endpoint_backoff = 60
+api_user = "user"
+api_password = "secret"
+"#;
+        let config = parse_config("test.toml", content).unwrap();
+        assert_eq!(config.endpoints.urls().len(), 2);
+        let now = std::time::Instant::now();
+        assert_eq!(config.endpoints.candidates(now), vec![0, 1]);
+        assert_eq!(config.endpoints.candidates(now), vec![1, 0]);
+
+        let content = content.replace("\"round-robin\"", "\"random\"");
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'endpoint_order'"));
+        let content = MINIMAL.replace("\"https://icinga/v1/actions/process-check-result\"", "[]");
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'api_url'"));
+    }
+
+    #[test]
+    fn test_parse_tls() {
+        let content = format!("{}insecure_skip_verify = true\nfingerprint = [\"{}\"]\n", MINIMAL, "ab".repeat(32));
+        let config_data: Value = toml::from_str(&content).unwrap();
+        let tls = parse_tls(&config_data["icinga"]).unwrap();
+        assert!(tls.insecure_skip_verify);
+        assert_eq!(tls.fingerprints, vec![[0xab; 32]]);
+        assert!(parse_config("test.toml", &content).is_ok());
+
+        let content = format!("{}client_cert = \"/etc/icinga/cert.pem\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("must be set together"));
+
+        let content = format!("{}fingerprint = \"abc\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("not a SHA-256 fingerprint"));
+
+        let content = format!("{}ca_file = \"/nonexistent/ca.pem\"\n", MINIMAL);
+        assert!(parse_config("test.toml", &content).unwrap_err().contains("'ca_file'"));
+    }
+
+    #[test]
+    fn test_invalid_toml() {
+        assert!(parse_config("test.toml", "[icinga").is_err());
     }
 }
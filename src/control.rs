@@ -1,12 +1,16 @@
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::io::{Read, Write};
 use std::path::PathBuf;
-use crate::checks::{self, CheckResult};
-use crate::config;
+use std::time::{Duration, Instant, SystemTime};
+use crate::checks::{self, CheckResult, State, SubmitAs};
+use crate::daemon::{self, Daemon, ExpectedReport, ScheduledPing, SharedDaemon};
+use crate::endpoints::Endpoints;
+use crate::{actions, perfdata, pings, sinks};
 
 pub const SOCKET_PATH: &str = "/run/icinga_passive_checks/control.sock";
+pub const CHECK_TYPE: &str = "Passive Command";
 
-pub fn start_control_socket() -> std::io::Result<()> {
+pub fn start_control_socket(daemon: SharedDaemon) -> std::io::Result<()> {
     let socket = PathBuf::from(SOCKET_PATH);
     if socket.exists() {
         std::fs::remove_file(&socket)?;
@@ -17,14 +21,19 @@ pub fn start_control_socket() -> std::io::Result<()> {
     for stream in listener.incoming() {
         match stream {
             Ok(mut stream) => {
-                let mut buffer = [0; 1024];
-                match stream.read(&mut buffer) {
-                    Ok(n) => {
-                        let command = String::from_utf8_lossy(&buffer[..n]);
-                        handle_command(&mut stream, &command);
+                // Runs take a while, answer each connection on its own thread
+                // so they don't hold up status queries and reports
+                let daemon = std::sync::Arc::clone(&daemon);
+                std::thread::spawn(move || {
+                    let mut buffer = [0; 1024];
+                    match stream.read(&mut buffer) {
+                        Ok(n) => {
+                            let command = String::from_utf8_lossy(&buffer[..n]);
+                            handle_command(&mut stream, &command, &daemon);
+                        }
+                        Err(e) => eprintln!("Failed to read from socket: {}", e),
                     }
-                    Err(e) => eprintln!("Failed to read from socket: {}", e),
-                }
+                });
             }
             Err(e) => eprintln!("Failed to accept connection: {}", e),
         }
@@ -32,30 +41,309 @@ pub fn start_control_socket() -> std::io::Result<()> {
     Ok(())
 }
 
-fn handle_command(stream: &mut UnixStream, command: &str) {
+fn handle_command(stream: &mut UnixStream, command: &str, daemon: &SharedDaemon) {
     let response = match command.trim().split('|').collect::<Vec<_>>().as_slice() {
         ["report", check_source, check_name, exit_status, plugin_output] => {
-            let config = config::load_config();
-            let mut check_data = CheckResult::new();
-            check_data.insert("exit_status".to_string(), exit_status.to_string());
-            check_data.insert("plugin_output".to_string(), plugin_output.to_string());
-            
-            checks::send_passive_check(
-                check_source,
-                check_name,
-                check_source, // use check_source as host since it's the hostname
-                "Passive Command",
-                &check_data,
-                &config
-            );
-            "report sent"
+            report_command(daemon, check_source, check_name, exit_status, plugin_output, None, false)
         }
-        _ => "unknown command",
+        ["report", check_source, check_name, exit_status, plugin_output, perf_data] => {
+            report_command(daemon, check_source, check_name, exit_status, plugin_output, Some(perf_data), false)
+        }
+        ["report-host", check_source, check_name, exit_status, plugin_output] => {
+            report_command(daemon, check_source, check_name, exit_status, plugin_output, None, true)
+        }
+        ["report-host", check_source, check_name, exit_status, plugin_output, perf_data] => {
+            report_command(daemon, check_source, check_name, exit_status, plugin_output, Some(perf_data), true)
+        }
+        ["reload"] => match daemon::reload(daemon) {
+            Ok(()) => "config reloaded".to_string(),
+            Err(e) => format!("reload failed: {}", e),
+        },
+        ["status"] => format_status(&daemon.lock().unwrap()),
+        ["list"] => format_list(&daemon.lock().unwrap()),
+        ["last", check_name] => {
+            let daemon = daemon.lock().unwrap();
+            match (daemon.find_ping(check_name), daemon.find_report(check_name)) {
+                (Some(scheduled), _) => format_last(scheduled),
+                (None, Some(expected)) => format_last_report(expected),
+                (None, None) => format!("unknown check: {}", check_name),
+            }
+        }
+        ["run", check_name] => run_command(daemon, Some(check_name), true),
+        ["run", check_name, "nosubmit"] => run_command(daemon, Some(check_name), false),
+        ["run-all"] => run_command(daemon, None, true),
+        ["run-all", "nosubmit"] => run_command(daemon, None, false),
+        ["ack", check_name, author, comment, options @ ..] => ack_command(daemon, check_name, author, comment, options),
+        _ => "unknown command".to_string(),
     };
     
     let _ = stream.write_all(response.as_bytes());
 }
 
+fn report_command(
+    daemon: &SharedDaemon,
+    check_source: &str,
+    check_name: &str,
+    exit_status: &str,
+    plugin_output: &str,
+    perf_data: Option<&str>,
+    host_check: bool,
+) -> String {
+    let state = exit_status.parse().map(State::from_code).unwrap_or(State::Unknown);
+    let mut check_data = CheckResult::new(state, plugin_output);
+    if let Some(perf_data) = perf_data {
+        match perfdata::parse(perf_data) {
+            Ok(perf_data) => check_data.perfdata = perf_data,
+            Err(e) => return format!("report rejected: {}", e),
+        }
+    }
+    match report(daemon, check_source, check_name, &check_data, host_check) {
+        Ok(()) => "report sent".to_string(),
+        Err(e) => format!("report failed: {}", e),
+    }
+}
+
+/// Submit a result reported by a script, shared by the control socket and
+/// the HTTP API. `host_check` submits it for the host named `check_name`
+/// instead of a service, as does `submit_as = "host"` in a `[[report]]`.
+pub fn report(
+    daemon: &SharedDaemon,
+    check_source: &str,
+    check_name: &str,
+    check_data: &CheckResult,
+    host_check: bool,
+) -> Result<(), String> {
+    let (config, options) = {
+        let mut daemon = daemon.lock().unwrap();
+        daemon.record_report(check_name, check_data);
+        let mut options = daemon.find_report(check_name)
+            .map(|expected| expected.report.submit.clone())
+            .unwrap_or_default();
+        if host_check {
+            options.submit_as = SubmitAs::Host;
+        }
+        (std::sync::Arc::clone(&daemon.config), options)
+    };
+    sinks::submit(
+        check_source,
+        check_name,
+        check_source, // use check_source as host since it's the hostname
+        CHECK_TYPE,
+        check_data,
+        &options,
+        &config
+    )
+}
+
+/// Acknowledge the problem of a check, `options` are `sticky` and
+/// `expire=<duration>`. Checks that aren't configured are taken to be
+/// reported ones, like in `report`.
+fn ack_command(daemon: &SharedDaemon, check_name: &str, author: &str, comment: &str, options: &[&str]) -> String {
+    let mut sticky = false;
+    let mut expiry = None;
+    for option in options {
+        match option.split_once('=') {
+            None if *option == "sticky" => sticky = true,
+            Some(("expire", duration)) => match actions::parse_duration(duration) {
+                Ok(duration) => expiry = Some(SystemTime::now() + duration),
+                Err(e) => return format!("ack rejected: {}", e),
+            },
+            _ => return format!("ack rejected: unknown option '{}'", option),
+        }
+    }
+    if comment.is_empty() {
+        return "ack rejected: a comment is required".to_string();
+    }
+
+    let config = std::sync::Arc::clone(&daemon.lock().unwrap().config);
+    let object = actions::check_target(&config, check_name).unwrap_or_else(|| {
+        let hostname = crate::get_hostname();
+        checks::check_object(&hostname, check_name, &hostname, CHECK_TYPE, &Default::default(), &config.names)
+    });
+    match actions::acknowledge_problem(&config, &object, author, comment, sticky, expiry) {
+        Ok(()) => format!("acknowledged {}", object),
+        Err(e) => format!("ack failed: {}", e),
+    }
+}
+
+fn run_command(daemon: &SharedDaemon, name: Option<&str>, submit: bool) -> String {
+    match daemon::run_now(daemon, name, submit) {
+        Ok(runs) => format_runs(&runs),
+        Err(e) => e,
+    }
+}
+
+pub fn format_runs(runs: &[daemon::CheckRun]) -> String {
+    if runs.is_empty() {
+        return "no checks configured".to_string();
+    }
+    runs.iter().map(|run| {
+        let submission = match &run.submission {
+            Some(submission) => format!("submission {}", format_submission(Some(submission))),
+            None => "not submitted".to_string(),
+        };
+        format!(
+            "{}: {} {}: {} | {} ({})",
+            pings::CHECK_TYPE,
+            run.name,
+            run.result.state,
+            run.result.output,
+            format_perfdata(&run.result),
+            submission,
+        )
+    }).collect::<Vec<_>>().join("\n")
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes, seconds) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn format_ago(time: Option<SystemTime>) -> String {
+    match time.and_then(|t| t.elapsed().ok()) {
+        Some(elapsed) => format!("{} ago", format_duration(elapsed)),
+        None => "never".to_string(),
+    }
+}
+
+fn format_next_run(next_run: Instant) -> String {
+    match next_run.checked_duration_since(Instant::now()) {
+        Some(remaining) => format!("in {}", format_duration(remaining)),
+        None => "now".to_string(),
+    }
+}
+
+fn format_submission(submission: Option<&Result<(), String>>) -> String {
+    match submission {
+        Some(Ok(())) => "ok".to_string(),
+        Some(Err(e)) => format!("failed ({})", e),
+        None => "none".to_string(),
+    }
+}
+
+fn format_state(result: Option<&CheckResult>) -> &'static str {
+    result.map_or("PENDING", |r| r.state.as_str())
+}
+
+fn format_perfdata(result: &CheckResult) -> String {
+    perfdata::format(&result.perfdata)
+}
+
+pub fn format_status(daemon: &Daemon) -> String {
+    let failed = daemon.pings.iter()
+        .map(|s| &s.last_submission)
+        .chain(daemon.reports.iter().map(|r| &r.last_submission))
+        .filter(|submission| matches!(submission, Some(Err(_))))
+        .count();
+    let overdue = daemon.reports.iter().filter(|r| r.is_overdue()).count();
+    [
+        format!("version: {}", env!("CARGO_PKG_VERSION")),
+        format!("uptime: {}", format_duration(daemon.started.elapsed())),
+        format!("config: {}", daemon.config.config_path),
+        format!("checks: {}", daemon.pings.len()),
+        format!("expected reports: {} ({} overdue)", daemon.reports.len(), overdue),
+        format!("failed submissions: {}", failed),
+        format!("undelivered results: {}", daemon.undelivered()),
+        format_submission_stats(&checks::submission_stats()),
+    ].into_iter().chain(format_endpoints(&daemon.config.endpoints)).collect::<Vec<_>>().join("\n")
+}
+
+fn format_endpoints(endpoints: &Endpoints) -> Vec<String> {
+    endpoints.urls().iter().zip(endpoints.health()).map(|(url, health)| {
+        match (health.retry_at, health.last_error) {
+            (Some(retry_at), Some(error)) => format!(
+                "endpoint {}: failing ({} in a row, next try {}): {}",
+                url, health.failures, format_next_run(retry_at), error,
+            ),
+            _ => format!("endpoint {}: ok", url),
+        }
+    }).collect()
+}
+
+fn format_submission_stats(stats: &checks::SubmissionStats) -> String {
+    let millis = |latency: Duration| format!("{}ms", latency.as_millis());
+    match (stats.last_latency, stats.average_latency()) {
+        (Some(last), Some(average)) => format!(
+            "submissions: {} sent, {} failed, latency last {} avg {} max {}",
+            stats.sent, stats.failed, millis(last), millis(average), millis(stats.max_latency),
+        ),
+        _ => "submissions: none".to_string(),
+    }
+}
+
+fn format_list(daemon: &Daemon) -> String {
+    if daemon.pings.is_empty() && daemon.reports.is_empty() {
+        return "no checks configured".to_string();
+    }
+    let reports = daemon.reports.iter().map(|r| {
+        format!(
+            "{}: {} expected every {}, last report {}{}",
+            CHECK_TYPE,
+            r.report.name,
+            format_duration(Duration::from_secs(r.report.interval)),
+            format_ago(r.last_report),
+            if r.is_overdue() { " (overdue)" } else { "" },
+        )
+    });
+    daemon.pings.iter().map(|s| {
+        format!(
+            "{}: {} ({}) state {}, last run {}, next run {}, submission {}",
+            pings::CHECK_TYPE,
+            s.ping.name,
+            s.ping.host,
+            format_state(s.last_result.as_ref()),
+            format_ago(s.last_run),
+            format_next_run(s.next_run),
+            format_submission(s.last_submission.as_ref()),
+        )
+    }).chain(reports).collect::<Vec<_>>().join("\n")
+}
+
+fn format_last(scheduled: &ScheduledPing) -> String {
+    let mut lines = vec![
+        format!("check: {}: {}", pings::CHECK_TYPE, scheduled.ping.name),
+        format!("host: {}", scheduled.ping.host),
+        format!("state: {}", format_state(scheduled.last_result.as_ref())),
+        format!("output: {}", scheduled.last_result.as_ref().map(|r| r.plugin_output()).unwrap_or_default()),
+        format!("performance data: {}", scheduled.last_result.as_ref().map(format_perfdata).unwrap_or_default()),
+        format!("last run: {}", format_ago(scheduled.last_run)),
+        format!("submission: {}", format_submission(scheduled.last_submission.as_ref())),
+        format!("next run: {}", format_next_run(scheduled.next_run)),
+    ];
+    if scheduled.ping.flapping.is_some() {
+        lines.push(format!(
+            "flapping: {} ({:.0}% state changes)",
+            if scheduled.flap.flapping { "yes" } else { "no" },
+            scheduled.flap.change_percent(),
+        ));
+    }
+    if scheduled.soft_attempts > 0 {
+        lines.push(format!("retrying: {} of {}", scheduled.soft_attempts, scheduled.ping.retries));
+    }
+    lines.join("\n")
+}
+
+fn format_last_report(expected: &ExpectedReport) -> String {
+    [
+        format!("check: {}: {}", CHECK_TYPE, expected.report.name),
+        format!("expected every: {}", format_duration(Duration::from_secs(expected.report.interval))),
+        format!("last report: {}", format_ago(expected.last_report)),
+        format!("state: {}", format_state(expected.last_result.as_ref())),
+        format!("output: {}", expected.last_result.as_ref().map(|r| r.plugin_output()).unwrap_or_default()),
+        format!("overdue: {}", if expected.is_overdue() { "yes" } else { "no" }),
+        format!("missing report submission: {}", format_submission(expected.last_submission.as_ref())),
+    ].join("\n")
+}
+
 pub fn send_command(command: &str) -> std::io::Result<String> {
     let mut stream = UnixStream::connect(SOCKET_PATH)?;
     stream.write_all(command.as_bytes())?;
@@ -64,3 +352,80 @@ pub fn send_command(command: &str) -> std::io::Result<String> {
     stream.read_to_string(&mut response)?;
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 7)), "3h 0m 7s");
+        assert_eq!(format_duration(Duration::from_secs(2 * 86400 + 3600)), "2d 1h 0m 0s");
+    }
+
+    #[test]
+    fn test_format_submission_stats() {
+        let mut stats = checks::SubmissionStats::default();
+        assert_eq!(format_submission_stats(&stats), "submissions: none");
+
+        stats.sent = 4;
+        stats.failed = 1;
+        stats.total_latency = Duration::from_millis(100);
+        stats.last_latency = Some(Duration::from_millis(12));
+        stats.max_latency = Duration::from_millis(40);
+        assert_eq!(
+            format_submission_stats(&stats),
+            "submissions: 4 sent, 1 failed, latency last 12ms avg 25ms max 40ms"
+        );
+    }
+
+    #[test]
+    fn test_ack_command_rejects_bad_options() {
+        let daemon = crate::daemon::tests::shared("");
+        assert_eq!(
+            ack_command(&daemon, "zpool", "alice", "fixing it", &["expire=soon"]),
+            "ack rejected: invalid duration 'soon', expected e.g. 90, 30m, 1h30m or 2d"
+        );
+        assert_eq!(
+            ack_command(&daemon, "zpool", "alice", "fixing it", &["forever"]),
+            "ack rejected: unknown option 'forever'"
+        );
+        assert_eq!(ack_command(&daemon, "zpool", "alice", "", &[]), "ack rejected: a comment is required");
+    }
+
+    #[test]
+    fn test_run_command_targets() {
+        let daemon = crate::daemon::tests::shared("");
+        let command = |command: &str| {
+            let (mut ours, mut theirs) = UnixStream::pair().unwrap();
+            handle_command(&mut theirs, command, &daemon);
+            drop(theirs);
+            let mut response = String::new();
+            ours.read_to_string(&mut response).unwrap();
+            response
+        };
+        // A check may be called --all, only run-all runs everything
+        assert_eq!(command("run|--all"), "unknown check: --all");
+        assert_eq!(command("run-all|nosubmit"), "no checks configured");
+    }
+
+    #[test]
+    fn test_format_endpoints() {
+        let urls = vec!["https://master1".to_string(), "https://master2".to_string()];
+        let endpoints = Endpoints::new(urls, Default::default(), Duration::from_secs(600));
+        endpoints.record_failure(0, "request failed", Instant::now());
+        let lines = format_endpoints(&endpoints);
+        assert!(lines[0].starts_with("endpoint https://master1: failing (1 in a row, next try in "), "{}", lines[0]);
+        assert!(lines[0].ends_with("): request failed"));
+        assert_eq!(lines[1], "endpoint https://master2: ok");
+    }
+
+    #[test]
+    fn test_format_state() {
+        let result = CheckResult::new(State::Critical, "PING CRITICAL");
+        assert_eq!(format_state(Some(&result)), "CRITICAL");
+        assert_eq!(format_state(None), "PENDING");
+    }
+}
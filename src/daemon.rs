@@ -0,0 +1,765 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use crate::checks::{CheckResult, IcingaObject, State};
+use crate::config::{self, IcingaConfig, PingConfig, ReportConfig};
+use crate::flapping::FlapState;
+use chrono::{DateTime, Utc};
+use crate::maintenance::{self, Maintenance, MaintenanceAction, Window};
+use crate::{control, health, heartbeats, pings, sinks};
+
+const TICK: Duration = Duration::from_secs(1);
+
+pub type SharedDaemon = Arc<Mutex<Daemon>>;
+
+#[derive(Debug)]
+pub struct ScheduledPing {
+    pub ping: PingConfig,
+    pub next_run: Instant,
+    pub last_run: Option<SystemTime>,
+    pub last_result: Option<CheckResult>,
+    pub last_submission: Option<Result<(), String>>,
+    /// Scheduled runs since the last submission whose unchanged result was
+    /// not submitted
+    pub unsubmitted: u64,
+    /// Re-runs so far of a problem that isn't submitted yet
+    pub soft_attempts: u32,
+    pub flap: FlapState,
+}
+
+impl ScheduledPing {
+    fn new(ping: PingConfig, next_run: Instant) -> Self {
+        ScheduledPing {
+            ping,
+            next_run,
+            last_run: None,
+            last_result: None,
+            last_submission: None,
+            unsubmitted: 0,
+            soft_attempts: 0,
+            flap: FlapState::default(),
+        }
+    }
+
+    /// Whether a scheduled result has to be submitted: when the state
+    /// changed, the last submission failed or `refresh_every` runs have
+    /// passed since the last one.
+    fn needs_submission(&self, result: &CheckResult) -> bool {
+        let changed = self.last_result.as_ref().map(|last| last.state) != Some(result.state);
+        let failed = !matches!(self.last_submission, Some(Ok(())));
+        changed || failed || self.unsubmitted + 1 >= self.ping.refresh_every
+    }
+}
+
+/// Dead-man's switch for a check reported through the control socket.
+#[derive(Debug)]
+pub struct ExpectedReport {
+    pub report: ReportConfig,
+    pub watched_since: SystemTime,
+    pub last_report: Option<SystemTime>,
+    /// The last reported result, or the one submitted for a missing report
+    pub last_result: Option<CheckResult>,
+    pub deadline: Instant,
+    pub last_submission: Option<Result<(), String>>,
+}
+
+impl ExpectedReport {
+    fn new(report: ReportConfig, now: Instant) -> Self {
+        let deadline = now + Duration::from_secs(report.interval);
+        ExpectedReport {
+            report,
+            watched_since: SystemTime::now(),
+            last_report: None,
+            last_result: None,
+            deadline,
+            last_submission: None,
+        }
+    }
+
+    pub fn is_overdue(&self) -> bool {
+        self.last_report.unwrap_or(self.watched_since)
+            .elapsed()
+            .map(|silent| silent > Duration::from_secs(self.report.interval))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug)]
+pub struct Daemon {
+    pub config: Arc<IcingaConfig>,
+    pub pings: Vec<ScheduledPing>,
+    pub reports: Vec<ExpectedReport>,
+    pub started: Instant,
+    /// When the `Passive Agent: health` result is next submitted
+    pub health_next_run: Instant,
+    /// Downtimes scheduled for the current window of a maintenance, by
+    /// maintenance name, object and window start
+    pub downtimes_scheduled: Vec<(String, IcingaObject, DateTime<Utc>)>,
+    /// Downtimes that failed to schedule are retried after this
+    pub downtimes_retry_at: Option<DateTime<Utc>>,
+}
+
+impl Daemon {
+    pub fn new(config: IcingaConfig) -> Self {
+        let mut daemon = Daemon {
+            config: Arc::new(config.clone()),
+            pings: Vec::new(),
+            reports: Vec::new(),
+            started: Instant::now(),
+            health_next_run: Instant::now(),
+            downtimes_scheduled: Vec::new(),
+            downtimes_retry_at: None,
+        };
+        daemon.apply_config(config);
+        daemon
+    }
+
+    /// Swap in a new config. Checks with an identical definition keep their
+    /// place in the schedule, new or changed checks run on the next tick.
+    pub fn apply_config(&mut self, config: IcingaConfig) {
+        let now = Instant::now();
+        let mut old_pings = std::mem::take(&mut self.pings);
+
+        self.pings = config.pings.iter().map(|ping| {
+            old_pings.iter()
+                .position(|old| old.ping == *ping)
+                .map(|i| old_pings.swap_remove(i))
+                .unwrap_or_else(|| ScheduledPing::new(ping.clone(), now))
+        }).collect();
+
+        // Reports are matched by name so a changed interval doesn't forget
+        // when the check was last reported.
+        let mut old_reports = std::mem::take(&mut self.reports);
+        self.reports = config.reports.iter().map(|report| {
+            match old_reports.iter().position(|old| old.report.name == report.name) {
+                Some(i) => {
+                    let mut expected = old_reports.swap_remove(i);
+                    if expected.report != *report {
+                        expected.deadline = now + Duration::from_secs(report.interval);
+                        expected.report = report.clone();
+                    }
+                    expected
+                }
+                None => ExpectedReport::new(report.clone(), now),
+            }
+        }).collect();
+
+        self.config = Arc::new(config);
+    }
+
+    /// Re-read the config from the path it was originally loaded from. The
+    /// running config is left untouched if the new one fails to load.
+    pub fn reload(&mut self) -> Result<(), String> {
+        let config = config::read_config(&self.config.config_path)?;
+        if config.debug {
+            println!("Config: {:#?}", config);
+        }
+        self.apply_config(config);
+        Ok(())
+    }
+
+    fn take_due(&mut self, now: Instant) -> Vec<PingConfig> {
+        let interval = Duration::from_secs(self.config.sleep_duration);
+        self.pings.iter_mut()
+            .filter(|scheduled| scheduled.next_run <= now)
+            .map(|scheduled| {
+                scheduled.next_run = now + interval;
+                scheduled.ping.clone()
+            })
+            .collect()
+    }
+
+    fn record(&mut self, name: &str, result: CheckResult, submission: Result<(), String>) {
+        if let Some(scheduled) = self.pings.iter_mut().find(|s| s.ping.name == name) {
+            scheduled.last_run = Some(SystemTime::now());
+            scheduled.last_result = Some(result);
+            scheduled.last_submission = Some(submission);
+            scheduled.unsubmitted = 0;
+        }
+    }
+
+    /// Hold back a problem of a ping whose last result was OK, scheduling a
+    /// re-run after `retry_interval`, until it has failed `retries` more
+    /// times. Returns the attempt if the result was held back.
+    fn hold_soft_problem(&mut self, name: &str, result: &CheckResult, now: Instant) -> Option<u32> {
+        let scheduled = self.pings.iter_mut().find(|s| s.ping.name == name)?;
+        let was_ok = scheduled.last_result.as_ref().is_none_or(|last| last.state == State::Ok);
+        if result.state == State::Ok || !was_ok || scheduled.soft_attempts >= scheduled.ping.retries {
+            scheduled.soft_attempts = 0;
+            return None;
+        }
+        scheduled.soft_attempts += 1;
+        scheduled.last_run = Some(SystemTime::now());
+        scheduled.next_run = now + Duration::from_secs(scheduled.ping.retry_interval);
+        Some(scheduled.soft_attempts)
+    }
+
+    /// Feed a scheduled result into the ping's flap detection, which
+    /// annotates it (and with `dampen` pins its state) while flapping.
+    fn track_flapping(&mut self, name: &str, result: &mut CheckResult) {
+        let Some(scheduled) = self.pings.iter_mut().find(|s| s.ping.name == name) else {
+            return;
+        };
+        let Some(config) = &scheduled.ping.flapping else {
+            return;
+        };
+        let was_flapping = scheduled.flap.flapping;
+        scheduled.flap.record(config, result);
+        match (was_flapping, scheduled.flap.flapping) {
+            (false, true) => println!("Ping {} started flapping ({:.0}% state changes)", name, scheduled.flap.change_percent()),
+            (true, false) => println!("Ping {} stopped flapping", name),
+            _ => {}
+        }
+    }
+
+    /// Note the resubmission of a skipped ping's last result, which stays
+    /// its last result.
+    fn record_refresh(&mut self, name: &str, submission: Result<(), String>) {
+        if let Some(scheduled) = self.pings.iter_mut().find(|s| s.ping.name == name) {
+            scheduled.last_submission = Some(submission);
+            scheduled.unsubmitted = 0;
+        }
+    }
+
+    fn record_unsubmitted(&mut self, name: &str, result: CheckResult) {
+        if let Some(scheduled) = self.pings.iter_mut().find(|s| s.ping.name == name) {
+            scheduled.last_run = Some(SystemTime::now());
+            scheduled.last_result = Some(result);
+            scheduled.unsubmitted += 1;
+        }
+    }
+
+    pub fn find_ping(&self, name: &str) -> Option<&ScheduledPing> {
+        self.pings.iter().find(|s| s.ping.name == name)
+    }
+
+    pub fn find_report(&self, name: &str) -> Option<&ExpectedReport> {
+        self.reports.iter().find(|r| r.report.name == name)
+    }
+
+    /// Results Icinga doesn't have yet: the unchanged runs held back by
+    /// `refresh_every` and the latest result of each check whose submission
+    /// failed.
+    pub fn undelivered(&self) -> u64 {
+        let held_back: u64 = self.pings.iter().map(|s| s.unsubmitted).sum();
+        let failed = self.pings.iter().map(|s| &s.last_submission)
+            .chain(self.reports.iter().map(|r| &r.last_submission))
+            .filter(|submission| matches!(submission, Some(Err(_))))
+            .count();
+        held_back + failed as u64
+    }
+
+    /// Note that a report arrived through the control socket, pushing its
+    /// deadline forward. Reports without a configured interval are ignored.
+    pub fn record_report(&mut self, name: &str, result: &CheckResult) {
+        if let Some(expected) = self.reports.iter_mut().find(|r| r.report.name == name) {
+            expected.last_report = Some(SystemTime::now());
+            expected.last_result = Some(result.clone());
+            expected.deadline = Instant::now() + Duration::from_secs(expected.report.interval);
+        }
+    }
+
+    /// Overdue reports and the time they have been silent since. The
+    /// deadline is pushed forward so the problem is re-submitted once per
+    /// interval for as long as the reports stay away.
+    fn take_overdue(&mut self, now: Instant) -> Vec<(ReportConfig, SystemTime)> {
+        self.reports.iter_mut()
+            .filter(|expected| expected.deadline <= now)
+            .map(|expected| {
+                expected.deadline = now + Duration::from_secs(expected.report.interval);
+                let since = expected.last_report.unwrap_or(expected.watched_since);
+                (expected.report.clone(), since)
+            })
+            .collect()
+    }
+
+    /// The agent's own health result if it is enabled and due.
+    fn take_health(&mut self, now: Instant) -> Option<CheckResult> {
+        if self.config.health.is_none() || self.health_next_run > now {
+            return None;
+        }
+        self.health_next_run = now + Duration::from_secs(self.config.sleep_duration);
+        Some(health::health_result(self, now))
+    }
+
+    /// Downtimes of maintenance windows with the `downtime` action that
+    /// started and aren't scheduled yet, one per covered check.
+    fn take_due_downtimes(&mut self, now: DateTime<Utc>) -> Vec<(Maintenance, Window, IcingaObject)> {
+        if self.downtimes_retry_at.is_some_and(|retry_at| now < retry_at) {
+            return Vec::new();
+        }
+        let config = Arc::clone(&self.config);
+        let mut due = Vec::new();
+        for m in config.maintenance.iter().filter(|m| m.action == MaintenanceAction::Downtime) {
+            let Some(window) = m.active_window(now) else {
+                continue;
+            };
+            // Downtimes of earlier windows are done with
+            self.downtimes_scheduled.retain(|(name, _, start)| *name != m.name || *start == window.0);
+            for object in maintenance::downtime_objects(&config, m) {
+                if !self.downtimes_scheduled.iter().any(|(name, scheduled, _)| *name == m.name && *scheduled == object) {
+                    due.push((m.clone(), window, object));
+                }
+            }
+        }
+        due
+    }
+
+    /// Note a downtime scheduled for a maintenance window, so it isn't
+    /// scheduled again during that window.
+    fn record_downtime(&mut self, name: &str, object: IcingaObject, start: DateTime<Utc>) {
+        self.downtimes_scheduled.push((name.to_string(), object, start));
+    }
+
+    /// Note a submission for an expected report, `result` replaces the last
+    /// one unless it is `None` for a resubmitted one.
+    fn record_missing(&mut self, name: &str, result: Option<CheckResult>, submission: Result<(), String>) {
+        if let Some(expected) = self.reports.iter_mut().find(|r| r.report.name == name) {
+            if result.is_some() {
+                expected.last_result = result;
+            }
+            expected.last_submission = Some(submission);
+        }
+    }
+}
+
+/// Outcome of running a single check, `submission` is `None` when the
+/// result was not sent to Icinga.
+#[derive(Debug)]
+pub struct CheckRun {
+    pub name: String,
+    pub result: CheckResult,
+    pub submission: Option<Result<(), String>>,
+}
+
+/// Run a ping and optionally submit and record its result. Must be called
+/// without holding the daemon lock.
+pub fn run_ping(daemon: &SharedDaemon, config: &IcingaConfig, ping: &PingConfig, submit: bool) -> CheckRun {
+    let result = pings::execute_ping(&ping.host, &ping.thresholds);
+    let submission = if submit {
+        Some(submit_ping(daemon, config, ping, &result))
+    } else {
+        None
+    };
+    CheckRun { name: ping.name.clone(), result, submission }
+}
+
+fn submit_ping(daemon: &SharedDaemon, config: &IcingaConfig, ping: &PingConfig, result: &CheckResult) -> Result<(), String> {
+    let submission = sinks::submit(
+        &crate::get_hostname(),
+        &ping.name,
+        &ping.host,
+        pings::CHECK_TYPE,
+        result,
+        &ping.submit,
+        config,
+    );
+    daemon.lock().unwrap().record(&ping.name, result.clone(), submission.clone());
+    submission
+}
+
+/// Run a ping on its schedule, submitting the result only if it changed
+/// or is due for a refresh.
+fn run_scheduled_ping(daemon: &SharedDaemon, config: &IcingaConfig, ping: &PingConfig, note: Option<String>) {
+    let mut result = pings::execute_ping(&ping.host, &ping.thresholds);
+    let mut daemon_state = daemon.lock().unwrap();
+    if let Some(attempt) = daemon_state.hold_soft_problem(&ping.name, &result, Instant::now()) {
+        println!(
+            "Ping {} is {}, retry {} of {} in {}s before submitting it",
+            ping.name, result.state, attempt, ping.retries, ping.retry_interval
+        );
+        return;
+    }
+    daemon_state.track_flapping(&ping.name, &mut result);
+    if let Some(note) = note {
+        result.output = format!("{}: {}", note, result.output);
+    }
+    let needs_submission = daemon_state.find_ping(&ping.name)
+        .map(|scheduled| scheduled.needs_submission(&result))
+        .unwrap_or(true);
+    if needs_submission {
+        drop(daemon_state);
+        let _ = submit_ping(daemon, config, ping, &result);
+    } else {
+        if config.debug {
+            println!("Result of ping {} unchanged, not submitting it", ping.name);
+        }
+        daemon_state.record_unsubmitted(&ping.name, result);
+    }
+}
+
+/// Resubmit the last result of a ping skipped during maintenance so it
+/// doesn't go stale in Icinga. A ping that never ran has nothing to resubmit.
+fn refresh_skipped_ping(daemon: &SharedDaemon, config: &IcingaConfig, ping: &PingConfig, m: &Maintenance) {
+    let last = daemon.lock().unwrap().find_ping(&ping.name).and_then(|s| s.last_result.clone());
+    let Some(last) = last else {
+        println!("Skipping ping {} during maintenance '{}'", ping.name, m.name);
+        return;
+    };
+    let submission = sinks::submit(
+        &crate::get_hostname(),
+        &ping.name,
+        &ping.host,
+        pings::CHECK_TYPE,
+        &m.skipped_result(&last),
+        &ping.submit,
+        config,
+    );
+    daemon.lock().unwrap().record_refresh(&ping.name, submission);
+}
+
+/// Run checks out-of-band on request, without touching their schedule.
+/// Runs every configured check when `name` is `None`.
+pub fn run_now(daemon: &SharedDaemon, name: Option<&str>, submit: bool) -> Result<Vec<CheckRun>, String> {
+    let (config, selected) = {
+        let daemon = daemon.lock().unwrap();
+        let selected: Vec<PingConfig> = match name {
+            Some(name) => vec![daemon.find_ping(name)
+                .ok_or_else(|| format!("unknown check: {}", name))?
+                .ping.clone()],
+            None => daemon.pings.iter().map(|s| s.ping.clone()).collect(),
+        };
+        (Arc::clone(&daemon.config), selected)
+    };
+
+    Ok(selected.iter().map(|ping| run_ping(daemon, &config, ping, submit)).collect())
+}
+
+/// Reload the config and log the outcome, shared by SIGHUP and the control socket.
+pub fn reload(daemon: &SharedDaemon) -> Result<(), String> {
+    let result = daemon.lock().unwrap().reload();
+    match &result {
+        Ok(()) => println!("Configuration reloaded"),
+        Err(e) => eprintln!("Failed to reload configuration, keeping the old one: {}", e),
+    }
+    result
+}
+
+pub fn run(daemon: SharedDaemon, reload_requested: Arc<AtomicBool>) {
+    loop {
+        if reload_requested.swap(false, Ordering::Relaxed) {
+            let _ = reload(&daemon);
+        }
+
+        // Don't hold the lock while the checks run, the control socket
+        // needs it to answer commands.
+        let (config, due, overdue, downtimes) = {
+            let mut daemon = daemon.lock().unwrap();
+            let now = Instant::now();
+            let due = daemon.take_due(now);
+            let overdue = daemon.take_overdue(now);
+            let downtimes = daemon.take_due_downtimes(Utc::now());
+            (Arc::clone(&daemon.config), due, overdue, downtimes)
+        };
+
+        for (m, window, object) in downtimes {
+            match maintenance::schedule_downtime(&config, &m, &object, window) {
+                Ok(()) => daemon.lock().unwrap().record_downtime(&m.name, object, window.0),
+                Err(e) => {
+                    eprintln!("{}, retrying in {}s", e, config.sleep_duration);
+                    let retry_at = Utc::now() + chrono::TimeDelta::seconds(config.sleep_duration as i64);
+                    daemon.lock().unwrap().downtimes_retry_at = Some(retry_at);
+                }
+            }
+        }
+
+        for ping in due {
+            let note = match maintenance::find_active(&config.maintenance, &ping.name, Utc::now()) {
+                Some(m) if m.action == MaintenanceAction::Skip => {
+                    refresh_skipped_ping(&daemon, &config, &ping, m);
+                    continue;
+                }
+                Some(m) if m.action == MaintenanceAction::Note => Some(m.note()),
+                _ => None,
+            };
+            run_scheduled_ping(&daemon, &config, &ping, note);
+        }
+
+        for (report, since) in overdue {
+            let hostname = crate::get_hostname();
+            let missing = heartbeats::missing_report_result(&report, since);
+            // While skipped the last result is resubmitted instead, it stays
+            // the report's last result
+            let (result, replaces_last) = match maintenance::find_active(&config.maintenance, &report.name, Utc::now()) {
+                Some(m) if m.action == MaintenanceAction::Skip => {
+                    match daemon.lock().unwrap().find_report(&report.name).and_then(|r| r.last_result.as_ref()) {
+                        Some(last) => (m.skipped_result(last), false),
+                        None => {
+                            println!("Not submitting missing report {} during maintenance '{}'", report.name, m.name);
+                            continue;
+                        }
+                    }
+                }
+                Some(m) if m.action == MaintenanceAction::Note => {
+                    (CheckResult { output: format!("{}: {}", m.note(), missing.output), ..missing }, true)
+                }
+                _ => (missing, true),
+            };
+            let submission = sinks::submit(
+                &hostname,
+                &report.name,
+                &hostname,
+                control::CHECK_TYPE,
+                &result,
+                &report.submit,
+                &config,
+            );
+            daemon.lock().unwrap().record_missing(&report.name, replaces_last.then_some(result), submission);
+        }
+
+        // Checked after this tick's checks so their outcome is included
+        let due_health = daemon.lock().unwrap().take_health(Instant::now());
+        if let (Some(result), Some(health)) = (due_health, &config.health) {
+            let hostname = crate::get_hostname();
+            let _ = sinks::submit(
+                &hostname,
+                health::CHECK_NAME,
+                &hostname,
+                health::CHECK_TYPE,
+                &result,
+                &health.submit,
+                &config,
+            );
+        }
+
+        std::thread::sleep(TICK);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::endpoints::Endpoints;
+    use crate::sinks::{Sink, SinkConfig};
+
+    /// A daemon shared like the listeners get it, with the minimal config
+    /// followed by `extra`.
+    pub(crate) fn shared(extra: &str) -> SharedDaemon {
+        Arc::new(Mutex::new(Daemon::new(crate::config::tests::minimal(extra))))
+    }
+
+    fn config_with_pings(pings: &[(&str, &str)]) -> IcingaConfig {
+        IcingaConfig {
+            config_path: "test.toml".to_string(),
+            endpoints: Arc::new(Endpoints::new(
+                vec!["https://icinga".to_string()],
+                Default::default(),
+                Duration::from_secs(30),
+            )),
+            api_user: "user".to_string(),
+            api_password: "secret".to_string(),
+            debug: false,
+            pings: pings.iter().map(|(name, host)| PingConfig {
+                name: name.to_string(),
+                host: host.to_string(),
+                thresholds: Default::default(),
+                refresh_every: 1,
+                retries: 0,
+                retry_interval: 60,
+                flapping: None,
+                submit: Default::default(),
+            }).collect(),
+            reports: Vec::new(),
+            sleep_duration: 300,
+            http: None,
+            metrics: None,
+            health: None,
+            maintenance: Vec::new(),
+            names: Default::default(),
+            address_by: Default::default(),
+            client: Default::default(),
+            sinks: vec![SinkConfig { name: sinks::ICINGA_SINK.to_string(), sink: Sink::Icinga }],
+            default_sinks: vec![sinks::ICINGA_SINK.to_string()],
+        }
+    }
+
+    fn report(name: &str, interval: u64) -> ReportConfig {
+        ReportConfig { name: name.to_string(), interval, missing_status: 2, submit: Default::default() }
+    }
+
+    #[test]
+    fn test_apply_config_keeps_schedule_for_unchanged_checks() {
+        let mut daemon = Daemon::new(config_with_pings(&[("router", "10.0.0.1"), ("nas", "10.0.0.2")]));
+        let now = Instant::now();
+        assert_eq!(daemon.take_due(now).len(), 2);
+
+        daemon.apply_config(config_with_pings(&[
+            ("router", "10.0.0.1"),
+            ("nas", "10.0.0.3"),
+            ("web", "10.0.0.4"),
+        ]));
+
+        let due: Vec<String> = daemon.take_due(Instant::now()).into_iter().map(|p| p.name).collect();
+        assert_eq!(due, vec!["nas", "web"]);
+    }
+
+    #[test]
+    fn test_apply_config_drops_removed_checks() {
+        let mut daemon = Daemon::new(config_with_pings(&[("router", "10.0.0.1"), ("nas", "10.0.0.2")]));
+        daemon.apply_config(config_with_pings(&[("router", "10.0.0.1")]));
+        assert_eq!(daemon.pings.len(), 1);
+        assert_eq!(daemon.config.pings.len(), 1);
+    }
+
+    #[test]
+    fn test_apply_config_keeps_history_for_unchanged_checks() {
+        let mut daemon = Daemon::new(config_with_pings(&[("router", "10.0.0.1")]));
+        daemon.record("router", CheckResult::new(State::Ok, "PING OK"), Err("Icinga returned 500".to_string()));
+        daemon.apply_config(config_with_pings(&[("router", "10.0.0.1")]));
+        assert!(daemon.find_ping("router").unwrap().last_submission.as_ref().unwrap().is_err());
+
+        daemon.apply_config(config_with_pings(&[("router", "10.0.0.9")]));
+        assert!(daemon.find_ping("router").unwrap().last_submission.is_none());
+    }
+
+    #[test]
+    fn test_report_becomes_overdue() {
+        let mut config = config_with_pings(&[]);
+        config.reports = vec![report("zpool", 60)];
+        let mut daemon = Daemon::new(config);
+        let now = Instant::now();
+
+        assert!(daemon.take_overdue(now).is_empty());
+        assert_eq!(daemon.take_overdue(now + Duration::from_secs(61)).len(), 1);
+        // Only re-submitted once the next interval has passed
+        assert!(daemon.take_overdue(now + Duration::from_secs(62)).is_empty());
+    }
+
+    #[test]
+    fn test_record_report_pushes_deadline() {
+        let mut config = config_with_pings(&[]);
+        config.reports = vec![report("zpool", 60)];
+        let mut daemon = Daemon::new(config);
+        daemon.reports[0].deadline = Instant::now();
+
+        daemon.record_report("zpool", &CheckResult::new(State::Ok, "OK"));
+        daemon.record_report("unconfigured", &CheckResult::new(State::Ok, "OK"));
+        assert!(daemon.take_overdue(Instant::now()).is_empty());
+        assert!(daemon.find_report("zpool").unwrap().last_report.is_some());
+    }
+
+    #[test]
+    fn test_apply_config_keeps_last_report() {
+        let mut config = config_with_pings(&[]);
+        config.reports = vec![report("zpool", 60)];
+        let mut daemon = Daemon::new(config.clone());
+        daemon.record_report("zpool", &CheckResult::new(State::Ok, "OK"));
+
+        config.reports = vec![report("zpool", 120)];
+        daemon.apply_config(config);
+        let expected = daemon.find_report("zpool").unwrap();
+        assert!(expected.last_report.is_some());
+        assert_eq!(expected.report.interval, 120);
+    }
+
+    #[test]
+    fn test_unchanged_results_are_refreshed_every_n_runs() {
+        let mut config = config_with_pings(&[("router", "10.0.0.1")]);
+        config.pings[0].refresh_every = 3;
+        let mut daemon = Daemon::new(config);
+        let ok = CheckResult::new(State::Ok, "PING OK");
+        let critical = CheckResult::new(State::Critical, "PING CRITICAL");
+
+        assert!(daemon.pings[0].needs_submission(&ok));
+        daemon.record("router", ok.clone(), Ok(()));
+        assert!(!daemon.pings[0].needs_submission(&ok));
+        daemon.record_unsubmitted("router", ok.clone());
+        assert!(!daemon.pings[0].needs_submission(&ok));
+        assert!(daemon.pings[0].needs_submission(&critical));
+        daemon.record_unsubmitted("router", ok.clone());
+        assert!(daemon.pings[0].needs_submission(&ok));
+        assert_eq!(daemon.undelivered(), 2);
+
+        daemon.record("router", ok.clone(), Err("Icinga returned 500".to_string()));
+        assert!(daemon.pings[0].needs_submission(&ok));
+        assert_eq!(daemon.undelivered(), 1);
+        daemon.record("router", ok.clone(), Ok(()));
+        assert_eq!(daemon.undelivered(), 0);
+    }
+
+    #[test]
+    fn test_refresh_keeps_last_result() {
+        let mut daemon = Daemon::new(config_with_pings(&[("router", "10.0.0.1")]));
+        daemon.record("router", CheckResult::new(State::Ok, "PING OK"), Err("Icinga returned 500".to_string()));
+        daemon.record_refresh("router", Ok(()));
+        assert_eq!(daemon.pings[0].last_result.as_ref().unwrap().output, "PING OK");
+        assert_eq!(daemon.pings[0].last_submission, Some(Ok(())));
+    }
+
+    #[test]
+    fn test_problems_are_retried_before_submitting() {
+        let mut config = config_with_pings(&[("router", "10.0.0.1")]);
+        config.pings[0].retries = 2;
+        config.pings[0].retry_interval = 10;
+        let mut daemon = Daemon::new(config);
+        let ok = CheckResult::new(State::Ok, "PING OK");
+        let critical = CheckResult::new(State::Critical, "PING CRITICAL");
+        let now = Instant::now();
+
+        assert_eq!(daemon.hold_soft_problem("router", &critical, now), Some(1));
+        assert_eq!(daemon.pings[0].next_run, now + Duration::from_secs(10));
+        // Recovering during the retries starts over
+        assert_eq!(daemon.hold_soft_problem("router", &ok, now), None);
+        assert_eq!(daemon.hold_soft_problem("router", &critical, now), Some(1));
+        assert_eq!(daemon.hold_soft_problem("router", &critical, now), Some(2));
+        assert_eq!(daemon.hold_soft_problem("router", &critical, now), None);
+
+        // Once the problem is submitted further results go through as well
+        daemon.record("router", critical.clone(), Ok(()));
+        assert_eq!(daemon.hold_soft_problem("router", &critical, now), None);
+    }
+
+    #[test]
+    fn test_health_is_due_every_interval() {
+        let mut config = config_with_pings(&[]);
+        assert!(Daemon::new(config.clone()).take_health(Instant::now()).is_none());
+
+        config.health = Some(config::HealthConfig { submit: Default::default(), undelivered_warning: None, undelivered_critical: None });
+        let mut daemon = Daemon::new(config);
+        let now = Instant::now();
+        assert!(daemon.take_health(now).is_some());
+        assert!(daemon.take_health(now + Duration::from_secs(299)).is_none());
+        assert!(daemon.take_health(now + Duration::from_secs(300)).is_some());
+    }
+
+    #[test]
+    fn test_downtimes_are_scheduled_once_per_window() {
+        let mut config = config_with_pings(&[("nas", "10.0.0.2")]);
+        let window: toml::Value = toml::from_str(
+            "name = \"backup\"\ncron = \"0 * * * *\"\nduration = 600\naction = \"downtime\"\ntimezone = \"UTC\"",
+        ).unwrap();
+        config.maintenance = vec![Maintenance::from_toml(&window).unwrap()];
+        let mut daemon = Daemon::new(config);
+        let at = |hour, minute| chrono::TimeZone::with_ymd_and_hms(&Utc, 2024, 7, 1, hour, minute, 0).unwrap();
+
+        assert!(daemon.take_due_downtimes(at(1, 30)).is_empty());
+        let due = daemon.take_due_downtimes(at(2, 0));
+        assert_eq!(due.len(), 1);
+        // Not scheduled yet, e.g. because Icinga couldn't be reached
+        assert_eq!(daemon.take_due_downtimes(at(2, 1)).len(), 1);
+        daemon.downtimes_retry_at = Some(at(2, 3));
+        assert!(daemon.take_due_downtimes(at(2, 2)).is_empty());
+
+        let (m, window, object) = due.into_iter().next().unwrap();
+        daemon.record_downtime(&m.name, object, window.0);
+        assert!(daemon.take_due_downtimes(at(2, 9)).is_empty());
+        assert_eq!(daemon.take_due_downtimes(at(3, 5)).len(), 1);
+    }
+
+    #[test]
+    fn test_run_now_unknown_check() {
+        let daemon = Arc::new(Mutex::new(Daemon::new(config_with_pings(&[("router", "10.0.0.1")]))));
+        let err = run_now(&daemon, Some("nas"), false).unwrap_err();
+        assert_eq!(err, "unknown check: nas");
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_old_config() {
+        let mut config = config_with_pings(&[("router", "10.0.0.1")]);
+        config.config_path = "/nonexistent/icinga_passive_checks.toml".to_string();
+        let mut daemon = Daemon::new(config);
+        assert!(daemon.reload().is_err());
+        assert_eq!(daemon.pings.len(), 1);
+        assert_eq!(daemon.config.pings[0].name, "router");
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest back-off as a multiple of the configured one.
+const MAX_BACKOFF_FACTOR: u32 = 16;
+
+/// In which order the configured Icinga API endpoints are tried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EndpointOrder {
+    /// Always start with the first endpoint, the others are fallbacks
+    #[default]
+    Failover,
+    /// Spread submissions over all endpoints
+    RoundRobin,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EndpointHealth {
+    /// Failures in a row, reset by a successful submission
+    pub failures: u32,
+    /// The endpoint is only tried once the healthy ones are exhausted until then
+    pub retry_at: Option<Instant>,
+    pub last_error: Option<String>,
+}
+
+/// The Icinga API URLs results are submitted to, with the health of each.
+/// A failing endpoint is backed off, doubling the wait with every failure
+/// in a row, so submissions go to the others in the meantime.
+#[derive(Debug)]
+pub struct Endpoints {
+    urls: Vec<String>,
+    order: EndpointOrder,
+    backoff: Duration,
+    health: Mutex<Vec<EndpointHealth>>,
+    next: AtomicUsize,
+}
+
+impl Endpoints {
+    pub fn new(urls: Vec<String>, order: EndpointOrder, backoff: Duration) -> Self {
+        let health = Mutex::new(vec![EndpointHealth::default(); urls.len()]);
+        Endpoints { urls, order, backoff, health, next: AtomicUsize::new(0) }
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.health.lock().unwrap().clone()
+    }
+
+    /// Indices of the endpoints in the order they should be tried for the
+    /// next submission. Endpoints that are backed off come last, soonest
+    /// retry first, so a result is never dropped without trying them all.
+    pub fn candidates(&self, now: Instant) -> Vec<usize> {
+        let start = match self.order {
+            EndpointOrder::Failover => 0,
+            EndpointOrder::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % self.urls.len().max(1),
+        };
+        let health = self.health.lock().unwrap();
+        let rotated = (0..self.urls.len()).map(|i| (start + i) % self.urls.len());
+        let (mut ready, mut waiting): (Vec<usize>, Vec<usize>) = rotated
+            .partition(|&i| health[i].retry_at.is_none_or(|retry_at| retry_at <= now));
+        waiting.sort_by_key(|&i| health[i].retry_at);
+        ready.append(&mut waiting);
+        ready
+    }
+
+    pub fn record_success(&self, index: usize) {
+        self.health.lock().unwrap()[index] = EndpointHealth::default();
+    }
+
+    pub fn record_failure(&self, index: usize, error: &str, now: Instant) {
+        let mut health = self.health.lock().unwrap();
+        let endpoint = &mut health[index];
+        endpoint.failures += 1;
+        let factor = 2u32.saturating_pow(endpoint.failures - 1).min(MAX_BACKOFF_FACTOR);
+        endpoint.retry_at = Some(now + self.backoff * factor);
+        endpoint.last_error = Some(error.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints(order: EndpointOrder) -> Endpoints {
+        let urls = vec!["https://a".to_string(), "https://b".to_string(), "https://c".to_string()];
+        Endpoints::new(urls, order, Duration::from_secs(30))
+    }
+
+    #[test]
+    fn test_failover_order() {
+        let endpoints = endpoints(EndpointOrder::Failover);
+        let now = Instant::now();
+        assert_eq!(endpoints.candidates(now), vec![0, 1, 2]);
+        assert_eq!(endpoints.candidates(now), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_round_robin_order() {
+        let endpoints = endpoints(EndpointOrder::RoundRobin);
+        let now = Instant::now();
+        assert_eq!(endpoints.candidates(now), vec![0, 1, 2]);
+        assert_eq!(endpoints.candidates(now), vec![1, 2, 0]);
+        assert_eq!(endpoints.candidates(now), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_failing_endpoint_is_backed_off() {
+        let endpoints = endpoints(EndpointOrder::Failover);
+        let now = Instant::now();
+        endpoints.record_failure(0, "request failed", now);
+        assert_eq!(endpoints.candidates(now), vec![1, 2, 0]);
+        assert_eq!(endpoints.candidates(now + Duration::from_secs(30)), vec![0, 1, 2]);
+
+        // The back-off doubles with every failure in a row
+        endpoints.record_failure(0, "request failed", now);
+        assert_eq!(endpoints.health()[0].retry_at, Some(now + Duration::from_secs(60)));
+        assert_eq!(endpoints.health()[0].failures, 2);
+
+        endpoints.record_success(0);
+        assert_eq!(endpoints.health()[0], EndpointHealth::default());
+        assert_eq!(endpoints.candidates(now), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_all_backed_off_soonest_first() {
+        let endpoints = endpoints(EndpointOrder::Failover);
+        let now = Instant::now();
+        endpoints.record_failure(1, "down", now);
+        endpoints.record_failure(0, "down", now + Duration::from_secs(5));
+        endpoints.record_failure(2, "down", now + Duration::from_secs(10));
+        assert_eq!(endpoints.candidates(now), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let endpoints = endpoints(EndpointOrder::Failover);
+        let now = Instant::now();
+        for _ in 0..40 {
+            endpoints.record_failure(0, "down", now);
+        }
+        assert_eq!(endpoints.health()[0].retry_at, Some(now + Duration::from_secs(30 * 16)));
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use toml::Value;
+use crate::checks::{CheckResult, State};
+
+/// Flap detection of a check, `flapping = { ... }` in a `[[ping]]`.
+///
+/// A check starts flapping when at least `high` percent of its last `window`
+/// results changed state, and stops once that drops to `low` percent or
+/// below, so it doesn't toggle in and out of flapping itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlapConfig {
+    pub window: usize,
+    pub low: f64,
+    pub high: f64,
+    /// Submit the worst state of the window while flapping
+    pub dampen: bool,
+}
+
+impl FlapConfig {
+    pub fn from_toml(value: &Value) -> Result<Self, String> {
+        let table = value.as_table().ok_or("flapping must be a table")?;
+        if let Some(key) = table.keys().find(|k| !["window", "low", "high", "dampen"].contains(&k.as_str())) {
+            return Err(format!("unknown flapping key '{}'", key));
+        }
+        let percent = |key: &str, default: f64| -> Result<f64, String> {
+            let value = match table.get(key) {
+                Some(Value::Integer(i)) => *i as f64,
+                Some(Value::Float(f)) => *f,
+                Some(_) => return Err(format!("'{}' must be a percentage", key)),
+                None => default,
+            };
+            if (0.0..=100.0).contains(&value) {
+                Ok(value)
+            } else {
+                Err(format!("'{}' must be between 0 and 100", key))
+            }
+        };
+        let window = match table.get("window").map(|v| v.as_integer()) {
+            Some(Some(window)) if (3..=100).contains(&window) => window as usize,
+            Some(_) => return Err("'window' must be a number of results from 3 to 100".to_string()),
+            None => 20,
+        };
+        let low = percent("low", 25.0)?;
+        let high = percent("high", 50.0)?;
+        if low > high {
+            return Err("'low' must not be above 'high'".to_string());
+        }
+        let dampen = match table.get("dampen") {
+            Some(value) => value.as_bool().ok_or("'dampen' must be true or false")?,
+            None => false,
+        };
+        Ok(FlapConfig { window, low, high, dampen })
+    }
+}
+
+/// The recent states of a check and whether it is flapping.
+#[derive(Debug, Clone, Default)]
+pub struct FlapState {
+    states: VecDeque<State>,
+    pub flapping: bool,
+}
+
+impl FlapState {
+    /// Percentage of the results in the window that changed state from the
+    /// one before.
+    pub fn change_percent(&self) -> f64 {
+        if self.states.len() < 2 {
+            return 0.0;
+        }
+        let changes = self.states.iter().zip(self.states.iter().skip(1))
+            .filter(|(a, b)| a != b)
+            .count();
+        changes as f64 * 100.0 / (self.states.len() - 1) as f64
+    }
+
+    fn worst(&self) -> State {
+        self.states.iter().fold(State::Ok, |worst, state| worst.worst(*state))
+    }
+
+    /// Add a result to the window and annotate it while the check is
+    /// flapping, pinning its state to the worst of the window with
+    /// `dampen`. Flapping is only decided once half the window is filled.
+    pub fn record(&mut self, config: &FlapConfig, result: &mut CheckResult) {
+        self.states.push_back(result.state);
+        while self.states.len() > config.window {
+            self.states.pop_front();
+        }
+
+        let percent = self.change_percent();
+        if self.states.len() * 2 >= config.window {
+            if percent >= config.high {
+                self.flapping = true;
+            } else if percent <= config.low {
+                self.flapping = false;
+            }
+        }
+        if !self.flapping {
+            return;
+        }
+
+        if config.dampen {
+            let worst = self.worst();
+            if worst != result.state {
+                result.output = format!("FLAPPING ({:.0}% state changes, holding {}): {}", percent, worst, result.output);
+                result.state = worst;
+                return;
+            }
+        }
+        result.output = format!("FLAPPING ({:.0}% state changes): {}", percent, result.output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dampen: bool) -> FlapConfig {
+        FlapConfig { window: 6, low: 25.0, high: 50.0, dampen }
+    }
+
+    fn record(flap: &mut FlapState, config: &FlapConfig, state: State) -> CheckResult {
+        let mut result = CheckResult::new(state, &format!("PING {}", state));
+        flap.record(config, &mut result);
+        result
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let value: Value = toml::from_str("window = 10\nhigh = 60\ndampen = true").unwrap();
+        assert_eq!(FlapConfig::from_toml(&value).unwrap(), FlapConfig { window: 10, low: 25.0, high: 60.0, dampen: true });
+
+        let value: Value = toml::from_str("low = 70").unwrap();
+        assert_eq!(FlapConfig::from_toml(&value).unwrap_err(), "'low' must not be above 'high'");
+        let value: Value = toml::from_str("windows = 10").unwrap();
+        assert!(FlapConfig::from_toml(&value).unwrap_err().contains("unknown flapping key"));
+    }
+
+    #[test]
+    fn test_flapping_with_hysteresis() {
+        let config = config(false);
+        let mut flap = FlapState::default();
+        assert_eq!(record(&mut flap, &config, State::Ok).output, "PING OK");
+        assert_eq!(record(&mut flap, &config, State::Critical).output, "PING CRITICAL");
+        assert!(!flap.flapping);
+        record(&mut flap, &config, State::Ok);
+        assert!(flap.flapping);
+        let result = record(&mut flap, &config, State::Critical);
+        assert_eq!(result.output, "FLAPPING (100% state changes): PING CRITICAL");
+        assert_eq!(result.state, State::Critical);
+
+        // Stays flapping until the changes drop to the low threshold
+        for _ in 0..3 {
+            record(&mut flap, &config, State::Critical);
+            assert!(flap.flapping);
+        }
+        assert_eq!(flap.change_percent(), 40.0);
+        record(&mut flap, &config, State::Critical);
+        assert!(!flap.flapping);
+    }
+
+    #[test]
+    fn test_dampen_holds_worst_state() {
+        let config = config(true);
+        let mut flap = FlapState::default();
+        for state in [State::Ok, State::Critical, State::Ok] {
+            record(&mut flap, &config, state);
+        }
+        let result = record(&mut flap, &config, State::Ok);
+        assert_eq!(result.state, State::Critical);
+        assert_eq!(result.output, "FLAPPING (67% state changes, holding CRITICAL): PING OK");
+    }
+}
@@ -0,0 +1,165 @@
+use std::time::{Duration, Instant};
+use crate::checks::{CheckResult, PerfData, State};
+use crate::daemon::Daemon;
+use crate::threshold::Range;
+
+pub const CHECK_TYPE: &str = "Passive Agent";
+pub const CHECK_NAME: &str = "health";
+
+/// The daemon's own health, submitted as `Passive Agent: health`.
+///
+/// WARNING when submissions fail or expected reports are overdue, CRITICAL
+/// when no submission works at all or the loop has fallen more than an
+/// interval behind on its pings. More undelivered results than the
+/// configured thresholds raise it too. A loop that is stuck entirely can't report
+/// anything, the TTL of the result makes Icinga notice that instead.
+pub fn health_result(daemon: &Daemon, now: Instant) -> CheckResult {
+    let interval = Duration::from_secs(daemon.config.sleep_duration);
+    let submissions: Vec<&Result<(), String>> = daemon.pings.iter()
+        .filter_map(|s| s.last_submission.as_ref())
+        .chain(daemon.reports.iter().filter_map(|r| r.last_submission.as_ref()))
+        .collect();
+    let failed = submissions.iter().filter(|submission| submission.is_err()).count();
+    let overdue = daemon.reports.iter().filter(|r| r.is_overdue()).count();
+    let late = daemon.pings.iter().filter(|s| s.next_run + interval < now).count();
+    let undelivered = daemon.undelivered();
+    let health = daemon.config.health.as_ref();
+    let above = |threshold: Option<u64>| threshold.is_some_and(|threshold| undelivered > threshold);
+    let backlog_critical = above(health.and_then(|h| h.undelivered_critical));
+    let backlog_warning = above(health.and_then(|h| h.undelivered_warning));
+
+    let state = if late > 0 || (failed > 0 && failed == submissions.len()) || backlog_critical {
+        State::Critical
+    } else if failed > 0 || overdue > 0 || backlog_warning {
+        State::Warning
+    } else {
+        State::Ok
+    };
+
+    let mut problems = Vec::new();
+    if failed > 0 {
+        problems.push(format!("{} of {} checks failed to submit", failed, submissions.len()));
+    }
+    if overdue > 0 {
+        problems.push(format!("{} expected reports overdue", overdue));
+    }
+    if late > 0 {
+        problems.push(format!("{} checks more than {}s behind schedule", late, interval.as_secs()));
+    }
+    if backlog_warning || backlog_critical {
+        problems.push(format!("{} results not delivered to Icinga", undelivered));
+    }
+    let summary = if problems.is_empty() {
+        format!("{} checks and {} expected reports running", daemon.pings.len(), daemon.reports.len())
+    } else {
+        problems.join(", ")
+    };
+
+    let mut result = CheckResult::new(state, &format!("AGENT {} - {}", state, summary));
+    result.long_output = Some([
+        format!("version: {}", env!("CARGO_PKG_VERSION")),
+        format!("uptime: {}s", daemon.started.elapsed().as_secs()),
+        format!("config: {}", daemon.config.config_path),
+    ].join("\n"));
+    result.perfdata = vec![
+        PerfData::new("checks", daemon.pings.len() as f64, "").with_min(0.0),
+        PerfData::new("expected_reports", daemon.reports.len() as f64, "").with_min(0.0),
+        PerfData::new("failed_submissions", failed as f64, "").with_min(0.0),
+        PerfData::new("overdue_reports", overdue as f64, "").with_min(0.0),
+        PerfData::new("late_checks", late as f64, "").with_min(0.0),
+        PerfData {
+            warn: health.and_then(|h| h.undelivered_warning).map(threshold_range),
+            crit: health.and_then(|h| h.undelivered_critical).map(threshold_range),
+            ..PerfData::new("undelivered_results", undelivered as f64, "").with_min(0.0)
+        },
+        PerfData::new("uptime", daemon.started.elapsed().as_secs() as f64, "s").with_min(0.0),
+    ];
+    result
+}
+
+/// The perfdata range alerting above `threshold`.
+fn threshold_range(threshold: u64) -> Range {
+    Range { start: Some(0.0), end: Some(threshold as f64), inside: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daemon() -> Daemon {
+        Daemon::new(crate::config::tests::minimal(r#"
+[daemon]
+sleep_duration = 60
+
+[[ping]]
+name = "router"
+host = "192.168.1.1"
+
+[[ping]]
+name = "nas"
+host = "192.168.1.20"
+"#))
+    }
+
+    #[test]
+    fn test_healthy() {
+        let mut daemon = daemon();
+        let now = Instant::now();
+        daemon.pings[0].last_submission = Some(Ok(()));
+        let result = health_result(&daemon, now);
+        assert_eq!(result.state, State::Ok);
+        assert_eq!(result.output, "AGENT OK - 2 checks and 0 expected reports running");
+        assert!(result.long_output.unwrap().starts_with("version: "));
+    }
+
+    #[test]
+    fn test_failed_submissions() {
+        let mut daemon = daemon();
+        let now = Instant::now();
+        daemon.pings[0].last_submission = Some(Ok(()));
+        daemon.pings[1].last_submission = Some(Err("Icinga returned 500".to_string()));
+        let result = health_result(&daemon, now);
+        assert_eq!(result.state, State::Warning);
+        assert_eq!(result.output, "AGENT WARNING - 1 of 2 checks failed to submit");
+
+        daemon.pings[0].last_submission = Some(Err("Icinga returned 500".to_string()));
+        assert_eq!(health_result(&daemon, now).state, State::Critical);
+    }
+
+    #[test]
+    fn test_undelivered_results() {
+        let mut daemon = daemon();
+        let now = Instant::now();
+        daemon.pings[0].last_submission = Some(Ok(()));
+        daemon.pings[1].last_submission = Some(Ok(()));
+        daemon.pings[0].unsubmitted = 3;
+        // Without thresholds the backlog is only in the performance data
+        let result = health_result(&daemon, now);
+        assert_eq!(result.state, State::Ok);
+        assert!(result.perfdata.iter().any(|p| p.label == "undelivered_results" && p.value == Some(3.0)));
+
+        let mut config = (*daemon.config).clone();
+        config.health = Some(crate::config::HealthConfig {
+            submit: Default::default(),
+            undelivered_warning: Some(2),
+            undelivered_critical: Some(5),
+        });
+        daemon.config = std::sync::Arc::new(config);
+        let result = health_result(&daemon, now);
+        assert_eq!(result.state, State::Warning);
+        assert_eq!(result.output, "AGENT WARNING - 3 results not delivered to Icinga");
+        let perf = result.perfdata.iter().find(|p| p.label == "undelivered_results").unwrap();
+        assert_eq!(perf.to_string(), "undelivered_results=3;2;5;0");
+
+        daemon.pings[1].unsubmitted = 3;
+        assert_eq!(health_result(&daemon, now).state, State::Critical);
+    }
+
+    #[test]
+    fn test_late_checks() {
+        let daemon = daemon();
+        let result = health_result(&daemon, Instant::now() + Duration::from_secs(61));
+        assert_eq!(result.state, State::Critical);
+        assert_eq!(result.output, "AGENT CRITICAL - 2 checks more than 60s behind schedule");
+    }
+}
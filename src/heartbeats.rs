@@ -0,0 +1,37 @@
+use chrono::{DateTime, Local};
+use std::time::SystemTime;
+use crate::checks::{CheckResult, State};
+use crate::config::ReportConfig;
+
+pub fn format_time(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Result submitted on behalf of a reported check that has gone silent.
+/// `since` is the last report, or when the daemon started watching the
+/// check if it has never been reported.
+pub fn missing_report_result(report: &ReportConfig, since: SystemTime) -> CheckResult {
+    CheckResult::new(State::from_code(report.missing_status), &format!(
+        "No report received since {} (expected every {} seconds)",
+        format_time(since), report.interval))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_report_result() {
+        let report = ReportConfig {
+            name: "zpool".to_string(),
+            interval: 3600,
+            missing_status: 3,
+            submit: Default::default(),
+        };
+        let result = missing_report_result(&report, SystemTime::now());
+        assert_eq!(result.state, State::Unknown);
+        let output = &result.output;
+        assert!(output.starts_with("No report received since "));
+        assert!(output.ends_with("(expected every 3600 seconds)"));
+    }
+}
@@ -0,0 +1,203 @@
+use serde::Deserialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::checks::{CheckResult, State};
+use crate::{control, perfdata};
+use crate::daemon::{self, SharedDaemon};
+
+#[derive(Deserialize)]
+struct ReportRequest {
+    check: String,
+    status: i32,
+    message: String,
+    host: Option<String>,
+    perfdata: Option<String>,
+    #[serde(default)]
+    host_check: bool,
+}
+
+pub fn start_http_api(daemon: SharedDaemon, listen: std::net::SocketAddr) -> Result<(), String> {
+    let server = Server::http(listen).map_err(|e| e.to_string())?;
+    println!("HTTP API listening on {}", listen);
+    // Plain HTTP sends the token in the clear
+    if !listen.ip().is_loopback() {
+        eprintln!("Warning: the HTTP API listens on {}, which isn't a loopback address, the token can be read on the network", listen);
+    }
+
+    serve(server, daemon);
+    Ok(())
+}
+
+fn serve(server: Server, daemon: SharedDaemon) {
+    for request in server.incoming_requests() {
+        // Runs and slow clients take a while, answer each request on its
+        // own thread like the control socket does
+        let daemon = std::sync::Arc::clone(&daemon);
+        std::thread::spawn(move || handle_request(request, &daemon));
+    }
+}
+
+fn handle_request(mut request: Request, daemon: &SharedDaemon) {
+    // The token is read on every request so a reload can rotate it
+    let token = daemon.lock().unwrap().config.http.as_ref().map(|http| http.token.clone());
+    let authorized = match (&token, bearer_token(&request)) {
+        (Some(expected), Some(given)) => {
+            ring::constant_time::verify_slices_are_equal(expected.as_bytes(), given.as_bytes()).is_ok()
+        }
+        _ => false,
+    };
+
+    let (status, body) = if !authorized {
+        (401, "unauthorized".to_string())
+    } else {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let submit = !query.split('&').any(|param| param == "submit=false");
+
+        match (request.method(), path) {
+            (Method::Get, "/v1/status") => (200, control::format_status(&daemon.lock().unwrap())),
+            (Method::Post, "/v1/report") => {
+                let mut body = String::new();
+                match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => handle_report(daemon, &body),
+                    Err(e) => (400, format!("failed to read request body: {}", e)),
+                }
+            }
+            (Method::Post, "/v1/run") => run(daemon, None, submit),
+            (Method::Post, path) if path.starts_with("/v1/run/") => {
+                match percent_decode(&path["/v1/run/".len()..]) {
+                    Some(name) => run(daemon, Some(&name), submit),
+                    None => (400, "invalid check name".to_string()),
+                }
+            }
+            _ => (404, "not found".to_string()),
+        }
+    };
+
+    let mut response = Response::from_string(body).with_status_code(status);
+    response.add_header(Header::from_bytes("Content-Type", "text/plain; charset=utf-8").unwrap());
+    if status == 401 {
+        response.add_header(Header::from_bytes("WWW-Authenticate", "Bearer").unwrap());
+    }
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send HTTP response: {}", e);
+    }
+}
+
+fn bearer_token(request: &Request) -> Option<String> {
+    request.headers().iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer ").map(|t| t.trim().to_string()))
+}
+
+/// Decode `%XX` escapes in a path segment, check names may contain spaces.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn handle_report(daemon: &SharedDaemon, body: &str) -> (u16, String) {
+    let report: ReportRequest = match serde_json::from_str(body) {
+        Ok(report) => report,
+        Err(e) => return (400, format!("invalid report: {}", e)),
+    };
+    let mut check_data = CheckResult::new(State::from_code(report.status), &report.message);
+    if let Some(perf_data) = &report.perfdata {
+        match perfdata::parse(perf_data) {
+            Ok(perf_data) => check_data.perfdata = perf_data,
+            Err(e) => return (400, format!("report rejected: {}", e)),
+        }
+    }
+    let host = report.host.unwrap_or_else(crate::get_hostname);
+    match control::report(daemon, &host, &report.check, &check_data, report.host_check) {
+        Ok(()) => (200, "report sent".to_string()),
+        Err(e) => (502, format!("report failed: {}", e)),
+    }
+}
+
+fn run(daemon: &SharedDaemon, name: Option<&str>, submit: bool) -> (u16, String) {
+    match daemon::run_now(daemon, name, submit) {
+        Ok(runs) => (200, control::format_runs(&runs)),
+        Err(e) => (404, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Serve the API on a free port, returning its base URL.
+    fn start() -> String {
+        let daemon = crate::daemon::tests::shared("\n[http]\ntoken = \"sekrit\"\n");
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || serve(server, daemon));
+        url
+    }
+
+    #[test]
+    fn test_unauthorized() {
+        let url = start();
+        let client = reqwest::blocking::Client::new();
+        let response = client.get(format!("{}/v1/status", url)).send().unwrap();
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+
+        let response = client.get(format!("{}/v1/status", url)).bearer_auth("sekrit2").send().unwrap();
+        assert_eq!(response.status(), 401);
+    }
+
+    #[test]
+    fn test_routing() {
+        let url = start();
+        let client = reqwest::blocking::Client::new();
+        let response = client.get(format!("{}/v1/status", url)).bearer_auth("sekrit").send().unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.text().unwrap().starts_with("version: "));
+
+        let response = client.post(format!("{}/v1/run?submit=false", url)).bearer_auth("sekrit").send().unwrap();
+        assert_eq!(response.text().unwrap(), "no checks configured");
+
+        let response = client.post(format!("{}/v1/run/my%20router", url)).bearer_auth("sekrit").send().unwrap();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.text().unwrap(), "unknown check: my router");
+
+        let response = client.get(format!("{}/v1/nothing", url)).bearer_auth("sekrit").send().unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn test_slow_request_doesnt_block_others() {
+        let url = start();
+        // A report whose body never arrives keeps its handler waiting
+        let mut stalled = std::net::TcpStream::connect(url.trim_start_matches("http://")).unwrap();
+        write!(stalled, "POST /v1/report HTTP/1.1\r\nAuthorization: Bearer sekrit\r\nContent-Length: 100000\r\n\r\n{{").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let client = reqwest::blocking::Client::builder().timeout(std::time::Duration::from_secs(5)).build().unwrap();
+        let response = client.get(format!("{}/v1/status", url)).bearer_auth("sekrit").send().unwrap();
+        assert_eq!(response.status(), 200);
+        drop(stalled);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("router").as_deref(), Some("router"));
+        assert_eq!(percent_decode("my%20router%2Fwan").as_deref(), Some("my router/wan"));
+        assert_eq!(percent_decode("%C3%BCber").as_deref(), Some("über"));
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(percent_decode("bad%+1"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+}
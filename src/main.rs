@@ -1,7 +1,9 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::env;
 use std::path::Path;
 use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 
 mod checks;
 mod pings;
@@ -9,6 +11,20 @@ mod config;
 mod update;
 mod systemd;
 mod control;
+mod daemon;
+mod heartbeats;
+mod http;
+mod perfdata;
+mod threshold;
+mod naming;
+mod tls;
+mod endpoints;
+mod sinks;
+mod metrics;
+mod health;
+mod flapping;
+mod actions;
+mod maintenance;
 
 fn get_hostname() -> String {
     env::var("HOSTNAME").unwrap_or_else(|_| {
@@ -19,6 +35,20 @@ fn get_hostname() -> String {
     })
 }
 
+fn get_fqdn() -> String {
+    static FQDN: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    FQDN.get_or_init(|| {
+        std::process::Command::new("hostname")
+            .arg("-f")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .filter(|fqdn| !fqdn.is_empty())
+            .unwrap_or_else(get_hostname)
+    }).clone()
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, arg_required_else_help(true))]
 struct Args {
@@ -53,6 +83,204 @@ struct Args {
     /// Message for control command
     #[arg(long, requires = "control")]
     message: Option<String>,
+
+    /// Performance data for control command, e.g. 'used=42%;80;90'
+    #[arg(long, requires = "control")]
+    perfdata: Option<String>,
+
+    /// Submit the control command result for the host named by --check
+    #[arg(long, requires = "control")]
+    host_check: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+// Commands that query the running daemon over the control socket
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show daemon version, uptime and submission health
+    Status,
+
+    /// List configured checks with their state and schedule
+    List,
+
+    /// Show the last result of a check
+    Last {
+        /// Check name
+        check: String,
+    },
+
+    /// Run a check right away and show the result
+    Run {
+        /// Check name
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        check: Option<String>,
+
+        /// Run every configured check
+        #[arg(long)]
+        all: bool,
+
+        /// Only show the result, don't submit it to Icinga
+        #[arg(long)]
+        no_submit: bool,
+    },
+
+    /// Acknowledge the current problem of a check in Icinga
+    Ack {
+        /// Check name, a configured ping or report or a reported check
+        #[arg(value_parser = control_field)]
+        check: String,
+
+        /// Comment shown in Icinga
+        #[arg(long, value_parser = control_field)]
+        comment: String,
+
+        /// Author shown in Icinga, defaults to the current user
+        #[arg(long, value_parser = control_field)]
+        author: Option<String>,
+
+        /// Keep the acknowledgement until the check is OK again, not just
+        /// until its next state change
+        #[arg(long)]
+        sticky: bool,
+
+        /// Remove the acknowledgement after this long, e.g. 30m or 4h
+        #[arg(long)]
+        expire: Option<String>,
+    },
+
+    /// Schedule or remove Icinga downtimes for this host or its services
+    Downtime {
+        #[command(subcommand)]
+        command: DowntimeCommand,
+    },
+}
+
+/// Fields of a control command are separated by `|`, so a value containing
+/// one would shift the fields after it.
+fn control_field(value: &str) -> Result<String, String> {
+    if value.contains(['|', '\n', '\r']) {
+        Err("can't contain '|' or line breaks".to_string())
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+// Downtimes go to the Icinga API directly, the daemon doesn't need to run
+#[derive(Subcommand, Debug)]
+enum DowntimeCommand {
+    /// Schedule a fixed downtime starting now
+    Schedule {
+        /// How long, e.g. 90 (seconds), 30m, 1h30m or 2d
+        #[arg(long)]
+        duration: String,
+
+        /// Comment shown in Icinga
+        #[arg(long)]
+        comment: String,
+
+        /// Author shown in Icinga, defaults to the current user
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Configured check whose service gets the downtime, can be repeated
+        #[arg(long)]
+        check: Vec<String>,
+
+        /// Icinga service of this host, can be repeated
+        #[arg(long)]
+        service: Vec<String>,
+
+        /// With neither --check nor --service, also cover all services of the host
+        #[arg(long, conflicts_with_all = ["check", "service"])]
+        all_services: bool,
+    },
+
+    /// Remove the downtimes of this host or of some of its services
+    Remove {
+        /// Configured check whose service's downtimes are removed, can be repeated
+        #[arg(long)]
+        check: Vec<String>,
+
+        /// Icinga service of this host, can be repeated
+        #[arg(long)]
+        service: Vec<String>,
+
+        /// With neither --check nor --service, also remove those of all services of the host
+        #[arg(long, conflicts_with_all = ["check", "service"])]
+        all_services: bool,
+    },
+}
+
+impl DowntimeCommand {
+    /// Request the downtimes of every target, a failing one doesn't stop
+    /// the others. Returns the failures.
+    fn run(&self, config: &config::IcingaConfig) -> Result<(), String> {
+        let mut failures = Vec::new();
+        match self {
+            DowntimeCommand::Schedule { duration, comment, author, check, service, all_services } => {
+                let duration = actions::parse_duration(duration)?;
+                let author = author.clone().unwrap_or_else(actions::default_author);
+                let start = std::time::SystemTime::now();
+                let end = chrono::DateTime::<chrono::Local>::from(start + duration);
+                for object in actions::resolve_targets(config, check, service)? {
+                    match actions::schedule_downtime(config, &object, start, start + duration, &author, comment, *all_services) {
+                        Ok(_) => println!("Scheduled a downtime for {} until {}", object, end.format("%Y-%m-%d %H:%M")),
+                        Err(e) => failures.push(e),
+                    }
+                }
+            }
+            DowntimeCommand::Remove { check, service, all_services } => {
+                for object in actions::resolve_targets(config, check, service)? {
+                    match actions::remove_downtimes(config, &object, *all_services) {
+                        Ok(removed) => println!("Removed {} downtime(s) of {}", removed, object),
+                        Err(e) => failures.push(e),
+                    }
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("\n"))
+        }
+    }
+}
+
+impl Command {
+    /// The control socket command, `None` for those that don't need the daemon.
+    fn control_command(&self) -> Option<String> {
+        let command = match self {
+            Command::Status => "status".to_string(),
+            Command::List => "list".to_string(),
+            Command::Last { check } => format!("last|{}", check),
+            Command::Run { check, all, no_submit } => {
+                let command = match (check, all) {
+                    (Some(check), false) => format!("run|{}", check),
+                    _ => "run-all".to_string(),
+                };
+                if *no_submit {
+                    format!("{}|nosubmit", command)
+                } else {
+                    command
+                }
+            }
+            Command::Ack { check, comment, author, sticky, expire } => {
+                let author = author.clone().unwrap_or_else(actions::default_author);
+                let mut command = format!("ack|{}|{}|{}", check, author, comment);
+                if *sticky {
+                    command.push_str("|sticky");
+                }
+                if let Some(expire) = expire {
+                    command.push_str(&format!("|expire={}", expire));
+                }
+                command
+            }
+            Command::Downtime { .. } => return None,
+        };
+        Some(command)
+    }
 }
 
 fn install_service() -> Result<(), std::io::Error> {
@@ -132,10 +360,31 @@ fn main() {
         return;
     }
 
+    if let Some(Command::Downtime { command }) = &args.command {
+        if let Err(e) = command.run(&config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(command) = args.command.as_ref().and_then(Command::control_command) {
+        match control::send_command(&command) {
+            Ok(response) => println!("{}", response),
+            Err(e) => eprintln!("Failed to send command: {}", e),
+        }
+        return;
+    }
+
     if args.control {
         let hostname = get_hostname();
         if let (Some(check), Some(status), Some(message)) = (args.check, args.status, args.message) {
-            let command = format!("report|{}|{}|{}|{}", hostname, check, status, message);
+            let kind = if args.host_check { "report-host" } else { "report" };
+            let mut command = format!("{}|{}|{}|{}|{}", kind, hostname, check, status, message);
+            if let Some(perf_data) = args.perfdata {
+                command.push('|');
+                command.push_str(&perf_data);
+            }
             match control::send_command(&command) {
                 Ok(response) => println!("Response: {}", response),
                 Err(e) => eprintln!("Failed to send command: {}", e),
@@ -148,19 +397,43 @@ fn main() {
 
     if args.daemon {
         println!("Running in daemon mode, I will run every {} seconds.", config.sleep_duration);
-        
+
+        let reload_requested = Arc::new(AtomicBool::new(false));
+        if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload_requested)) {
+            eprintln!("Failed to register SIGHUP handler: {}", e);
+        }
+
+        let daemon = Arc::new(Mutex::new(daemon::Daemon::new(config)));
+
         // Start control socket in a separate thread
-        std::thread::spawn(|| {
-            if let Err(e) = control::start_control_socket() {
+        let control_daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || {
+            if let Err(e) = control::start_control_socket(control_daemon) {
                 eprintln!("Failed to start control socket: {}", e);
             }
         });
 
-        loop {
-            for ping in &config.pings {
-                pings::ping_host(&get_hostname(), &ping.name, &ping.host, &config);
-            }
-            std::thread::sleep(std::time::Duration::from_secs(config.sleep_duration));
+        // The listener address is only read at startup, changing it needs a restart
+        let http_config = daemon.lock().unwrap().config.http.clone();
+        if let Some(http) = http_config {
+            let http_daemon = Arc::clone(&daemon);
+            std::thread::spawn(move || {
+                if let Err(e) = http::start_http_api(http_daemon, http.listen) {
+                    eprintln!("Failed to start HTTP API: {}", e);
+                }
+            });
         }
+
+        let metrics_config = daemon.lock().unwrap().config.metrics.clone();
+        if let Some(metrics) = metrics_config {
+            let metrics_daemon = Arc::clone(&daemon);
+            std::thread::spawn(move || {
+                if let Err(e) = metrics::start_metrics_listener(metrics_daemon, metrics.listen) {
+                    eprintln!("Failed to start metrics listener: {}", e);
+                }
+            });
+        }
+
+        daemon::run(daemon, reload_requested);
     }
 }
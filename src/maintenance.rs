@@ -0,0 +1,540 @@
+use std::time::SystemTime;
+use chrono::{DateTime, Datelike, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use toml::Value;
+use crate::actions;
+use crate::checks::{self, CheckResult, IcingaObject};
+use crate::config::IcingaConfig;
+use crate::{control, pings};
+
+/// Start and end of an occurrence of a maintenance window.
+pub type Window = (DateTime<Utc>, DateTime<Utc>);
+
+/// What happens to the checks of a maintenance window while it is active.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaintenanceAction {
+    /// Don't run the checks, resubmit their last result with the window
+    /// noted so it doesn't go stale in Icinga
+    Skip,
+    /// Submit the results with the window noted in the output
+    #[default]
+    Note,
+    /// Schedule an Icinga downtime for the window, checks run as usual
+    Downtime,
+}
+
+/// The time zone a window's times are in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    fn parse(zone: &str) -> Result<Self, String> {
+        let unsupported = || format!(
+            "unsupported timezone '{}', use \"local\", \"UTC\", a name like \"Europe/Berlin\" or an offset like \"+01:00\"",
+            zone
+        );
+        match zone {
+            "local" => Ok(Zone::Local),
+            "UTC" | "utc" => Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap())),
+            _ if !zone.starts_with(['+', '-']) => zone.parse().map(Zone::Named).map_err(|_| unsupported()),
+            _ => {
+                let (sign, rest) = match zone.split_at_checked(1) {
+                    Some(("+", rest)) => (1, rest),
+                    Some(("-", rest)) => (-1, rest),
+                    _ => return Err(unsupported()),
+                };
+                let (hours, minutes) = rest.split_once(':').ok_or_else(unsupported)?;
+                let hours: i32 = hours.parse().map_err(|_| unsupported())?;
+                let minutes: i32 = minutes.parse().map_err(|_| unsupported())?;
+                if minutes >= 60 {
+                    return Err(unsupported());
+                }
+                FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+                    .map(Zone::Fixed)
+                    .ok_or_else(unsupported)
+            }
+        }
+    }
+
+    fn local_time(self, now: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => now.with_timezone(&Local).naive_local(),
+            Zone::Fixed(offset) => now.with_timezone(&offset).naive_local(),
+            Zone::Named(zone) => now.with_timezone(&zone).naive_local(),
+        }
+    }
+
+    fn to_utc(self, time: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            Zone::Local => local_to_utc(&Local, time),
+            Zone::Fixed(offset) => (time - TimeDelta::seconds(offset.local_minus_utc() as i64)).and_utc(),
+            Zone::Named(zone) => local_to_utc(&zone, time),
+        }
+    }
+}
+
+/// The UTC time of a wall clock time in a zone with DST changes.
+fn local_to_utc<Z: TimeZone>(zone: &Z, time: NaiveDateTime) -> DateTime<Utc> {
+    match zone.from_local_datetime(&time) {
+        LocalResult::Single(time) => time.with_timezone(&Utc),
+        // Times repeated when DST ends count the first time
+        LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+        // Times skipped when DST starts move on by the hour that was skipped
+        LocalResult::None => zone.from_local_datetime(&(time + TimeDelta::hours(1))).earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_else(|| time.and_utc()),
+    }
+}
+
+/// A cron expression, `minute hour day-of-month month day-of-week`, each a
+/// list of `*`, values or ranges with an optional `/step`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&step| step > 0)
+                .ok_or_else(|| format!("invalid step in '{}'", part))?),
+            None => (part, 1),
+        };
+        let value = |v: &str| v.parse::<u32>().ok()
+            .filter(|v| (min..=max).contains(v))
+            .ok_or_else(|| format!("'{}' is not a value from {} to {}", v, min, max));
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // A single value with a step runs to the end, like `5/15`
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            return Err(format!("invalid range '{}'", range));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl std::str::FromStr for Cron {
+    type Err = String;
+
+    fn from_str(cron: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = cron.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("'{}' must have 5 fields: minute hour day month weekday", cron));
+        };
+        let mut weekday_bits = parse_cron_field(weekdays, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_cron_field(minutes, 0, 59)?,
+            hours: parse_cron_field(hours, 0, 23)?,
+            days: parse_cron_field(days, 1, 31)?,
+            months: parse_cron_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            // Like Vixie cron, `*/2` leaves the field unrestricted as well
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+fn has_bit(bits: u64, v: u32) -> bool {
+    bits & (1 << v) != 0
+}
+
+impl Cron {
+    #[cfg(test)]
+    fn matches(&self, time: NaiveDateTime) -> bool {
+        has_bit(self.minutes, time.minute()) && has_bit(self.hours, time.hour()) && self.matches_date(time.date())
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = has_bit(self.days, date.day());
+        let weekday = has_bit(self.weekdays, date.weekday().num_days_from_sunday());
+        // Like cron, a restricted day of month and day of week match either
+        let day_matches = if self.any_day || self.any_weekday { day && weekday } else { day || weekday };
+        has_bit(self.months, date.month()) && day_matches
+    }
+
+    /// The latest time at or before `time` the expression matches, if it is
+    /// after `limit`. Days, hours and minutes are searched field by field
+    /// instead of minute by minute.
+    fn previous(&self, time: NaiveDateTime, limit: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut date = time.date();
+        while date >= limit.date() {
+            if self.matches_date(date) {
+                let (last_hour, last_minute) = if date == time.date() { (time.hour(), time.minute()) } else { (23, 59) };
+                for hour in (0..=last_hour).rev().filter(|&hour| has_bit(self.hours, hour)) {
+                    let last_minute = if hour == last_hour { last_minute } else { 59 };
+                    if let Some(minute) = (0..=last_minute).rev().find(|&minute| has_bit(self.minutes, minute)) {
+                        let start = date.and_hms_opt(hour, minute, 0)?;
+                        return (start > limit).then_some(start);
+                    }
+                }
+            }
+            date = date.pred_opt()?;
+        }
+        None
+    }
+}
+
+/// When a maintenance window is active.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    /// From `start` to `end` on the given days, Monday first. An `end` before
+    /// `start` is on the next day.
+    Weekly { days: [bool; 7], start: NaiveTime, end: NaiveTime },
+    /// For `duration` seconds from every time the expression matches
+    Cron { cron: Cron, duration: u64 },
+}
+
+impl Schedule {
+    /// The window around `now` if there is one, in the schedule's local time.
+    fn window(&self, now: NaiveDateTime) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        match self {
+            Schedule::Weekly { days, start, end } => {
+                // A window that started yesterday may still be running
+                [now.date(), now.date().pred_opt()?].into_iter().find_map(|date| {
+                    if !days[date.weekday().num_days_from_monday() as usize] {
+                        return None;
+                    }
+                    let from = date.and_time(*start);
+                    let until = if end > start { date.and_time(*end) } else { date.succ_opt()?.and_time(*end) };
+                    (from <= now && now < until).then_some((from, until))
+                })
+            }
+            Schedule::Cron { cron, duration } => {
+                let duration = TimeDelta::seconds(*duration as i64);
+                let start = cron.previous(now, now - duration)?;
+                Some((start, start + duration))
+            }
+        }
+    }
+}
+
+/// A `[[maintenance]]` window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Maintenance {
+    pub name: String,
+    pub schedule: Schedule,
+    pub zone: Zone,
+    /// Names of the checks in the window, `*` matches anything. Empty for all.
+    pub checks: Vec<String>,
+    pub action: MaintenanceAction,
+    pub comment: Option<String>,
+}
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const WEEKDAY_NAMES: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+fn parse_time(table: &Value, key: &str) -> Result<NaiveTime, String> {
+    let time = table.get(key)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("missing '{}'", key))?;
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("'{}' must be a time like \"01:30\"", key))
+}
+
+fn string_list(table: &Value, key: &str) -> Result<Option<Vec<String>>, String> {
+    match table.get(key) {
+        Some(Value::Array(items)) => items.iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .map(Some)
+            .ok_or_else(|| format!("'{}' must be a list of strings", key)),
+        Some(_) => Err(format!("'{}' must be a list of strings", key)),
+        None => Ok(None),
+    }
+}
+
+impl Maintenance {
+    pub fn from_toml(table: &Value) -> Result<Self, String> {
+        let name = table.get("name")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing 'name' in a maintenance section")?
+            .to_string();
+        Self::parse(table, name.clone()).map_err(|e| format!("Invalid maintenance '{}': {}", name, e))
+    }
+
+    fn parse(table: &Value, name: String) -> Result<Self, String> {
+        let schedule = match table.get("cron") {
+            Some(cron) => {
+                let cron = cron.as_str().ok_or("'cron' must be a string")?.parse()?;
+                let duration = match table.get("duration").map(|v| v.as_integer()) {
+                    Some(Some(duration)) if (60..=7 * 24 * 3600).contains(&duration) => duration as u64,
+                    Some(_) => return Err("'duration' must be from 60 seconds to a week".to_string()),
+                    None => return Err("'cron' needs a 'duration' in seconds".to_string()),
+                };
+                Schedule::Cron { cron, duration }
+            }
+            None => {
+                let mut days = [true; 7];
+                if let Some(names) = string_list(table, "days")? {
+                    days = [false; 7];
+                    for day in names {
+                        let lowercase = day.to_lowercase();
+                        let index = WEEKDAYS.iter().zip(WEEKDAY_NAMES)
+                            .position(|(short, long)| lowercase == *short || lowercase == long)
+                            .ok_or_else(|| format!("unknown day '{}'", day))?;
+                        days[index] = true;
+                    }
+                }
+                let start = parse_time(table, "start")?;
+                let end = parse_time(table, "end")?;
+                if start == end {
+                    return Err("'start' and 'end' must differ".to_string());
+                }
+                Schedule::Weekly { days, start, end }
+            }
+        };
+
+        let zone = match table.get("timezone") {
+            Some(zone) => Zone::parse(zone.as_str().ok_or("'timezone' must be a string")?)?,
+            None => Zone::Local,
+        };
+        let action = match table.get("action").map(|v| v.as_str()) {
+            Some(Some("skip")) => MaintenanceAction::Skip,
+            Some(Some("note")) | None => MaintenanceAction::Note,
+            Some(Some("downtime")) => MaintenanceAction::Downtime,
+            Some(_) => return Err("'action' must be \"skip\", \"note\" or \"downtime\"".to_string()),
+        };
+        let comment = match table.get("comment") {
+            Some(comment) => Some(comment.as_str().ok_or("'comment' must be a string")?.to_string()),
+            None => None,
+        };
+
+        Ok(Maintenance {
+            name,
+            schedule,
+            zone,
+            checks: string_list(table, "checks")?.unwrap_or_default(),
+            action,
+            comment,
+        })
+    }
+
+    pub fn matches(&self, check: &str) -> bool {
+        self.checks.is_empty() || self.checks.iter().any(|pattern| glob_match(pattern, check))
+    }
+
+    /// The start and end of the window `now` is in, if any.
+    pub fn active_window(&self, now: DateTime<Utc>) -> Option<Window> {
+        let (start, end) = self.schedule.window(self.zone.local_time(now))?;
+        Some((self.zone.to_utc(start), self.zone.to_utc(end)))
+    }
+
+    pub fn note(&self) -> String {
+        format!("Maintenance '{}'", self.name)
+    }
+
+    /// The last result of a check skipped by the window, to resubmit as a
+    /// fresh one with the window noted.
+    pub fn skipped_result(&self, last: &CheckResult) -> CheckResult {
+        let mut result = last.clone();
+        let now = SystemTime::now();
+        result.execution_start = now;
+        result.execution_end = now;
+        result.output = format!("{}, not checked: {}", self.note(), last.output);
+        result
+    }
+}
+
+/// Match a name against a pattern where `*` stands for any text.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || !name[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// The first active maintenance window covering a check.
+pub fn find_active<'a>(maintenance: &'a [Maintenance], check: &str, now: DateTime<Utc>) -> Option<&'a Maintenance> {
+    maintenance.iter().find(|m| m.matches(check) && m.active_window(now).is_some())
+}
+
+/// The Icinga objects of the checks a maintenance window covers.
+pub fn downtime_objects(config: &IcingaConfig, maintenance: &Maintenance) -> Vec<IcingaObject> {
+    let hostname = crate::get_hostname();
+    config.pings.iter()
+        .filter(|ping| maintenance.matches(&ping.name))
+        .map(|ping| checks::check_object(
+            &hostname, &ping.name, &ping.host, pings::CHECK_TYPE, &ping.submit, &config.names,
+        ))
+        .chain(config.reports.iter()
+            .filter(|report| maintenance.matches(&report.name))
+            .map(|report| checks::check_object(
+                &hostname, &report.name, &hostname, control::CHECK_TYPE, &report.submit, &config.names,
+            )))
+        .collect()
+}
+
+/// Schedule an Icinga downtime for a check covered by a maintenance window.
+pub fn schedule_downtime(config: &IcingaConfig, maintenance: &Maintenance, object: &IcingaObject, window: Window) -> Result<(), String> {
+    let comment = maintenance.comment.clone()
+        .unwrap_or_else(|| format!("Maintenance window '{}'", maintenance.name));
+    let (start, end) = (SystemTime::from(window.0), SystemTime::from(window.1));
+    actions::schedule_downtime(config, object, start, end, actions::DEFAULT_AUTHOR, &comment, false)?;
+    println!(
+        "Scheduled a downtime for {} until {} (maintenance '{}')",
+        object, window.1.with_timezone(&Local).format("%Y-%m-%d %H:%M"), maintenance.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-07-01 is a Monday
+        NaiveDate::from_ymd_opt(2024, 7, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn maintenance(toml: &str) -> Result<Maintenance, String> {
+        Maintenance::from_toml(&toml::from_str(&format!("name = \"backup\"\n{}", toml)).unwrap())
+    }
+
+    #[test]
+    fn test_weekly_window_past_midnight() {
+        let m = maintenance("days = [\"sat\"]\nstart = \"23:00\"\nend = \"02:00\"").unwrap();
+        assert_eq!(m.schedule.window(at(6, 23, 30)), Some((at(6, 23, 0), at(7, 2, 0))));
+        assert_eq!(m.schedule.window(at(7, 1, 59)), Some((at(6, 23, 0), at(7, 2, 0))));
+        assert_eq!(m.schedule.window(at(7, 2, 0)), None);
+        assert_eq!(m.schedule.window(at(5, 23, 30)), None);
+    }
+
+    #[test]
+    fn test_cron() {
+        let cron: Cron = "30 1 * * 1-5".parse().unwrap();
+        assert!(cron.matches(at(1, 1, 30)));
+        assert!(!cron.matches(at(6, 1, 30)));
+        let cron: Cron = "*/15 * 1 * 0".parse().unwrap();
+        assert!(cron.matches(at(1, 3, 45)));
+        assert!(cron.matches(at(7, 3, 0)));
+        assert!(!cron.matches(at(2, 3, 0)));
+        assert!(!cron.matches(at(7, 3, 5)));
+        assert!("* * * *".parse::<Cron>().unwrap_err().contains("5 fields"));
+        assert!("61 * * * *".parse::<Cron>().unwrap_err().contains("from 0 to 59"));
+
+        let m = maintenance("cron = \"0 2 * * *\"\nduration = 5400\naction = \"note\"").unwrap();
+        assert_eq!(m.schedule.window(at(3, 3, 29)), Some((at(3, 2, 0), at(3, 3, 30))));
+        assert_eq!(m.schedule.window(at(3, 3, 30)), None);
+        assert_eq!(m.action, MaintenanceAction::Note);
+
+        // A stepped `*` leaves the day unrestricted, so both fields must match
+        let cron: Cron = "0 3 */2 * 1".parse().unwrap();
+        assert!(cron.matches(at(1, 3, 0)));
+        assert!(!cron.matches(at(3, 3, 0)));
+        assert!(!cron.matches(at(8, 3, 0)));
+    }
+
+    #[test]
+    fn test_cron_window_search() {
+        let m = maintenance("cron = \"0 23 * * *\"\nduration = 7200").unwrap();
+        assert_eq!(m.schedule.window(at(4, 0, 30)), Some((at(3, 23, 0), at(4, 1, 0))));
+        assert_eq!(m.schedule.window(at(4, 1, 0)), None);
+        assert_eq!(m.schedule.window(at(3, 23, 0)), Some((at(3, 23, 0), at(4, 1, 0))));
+
+        // The latest start wins, across days and within the hour
+        let m = maintenance("cron = \"10,40 8-17 * * 1\"\nduration = 604800").unwrap();
+        assert_eq!(m.schedule.window(at(1, 8, 39)), Some((at(1, 8, 10), at(8, 8, 10))));
+        assert_eq!(m.schedule.window(at(5, 3, 0)), Some((at(1, 17, 40), at(8, 17, 40))));
+        assert_eq!(m.schedule.window(at(8, 8, 9)), Some((at(1, 17, 40), at(8, 17, 40))));
+        assert_eq!(m.schedule.window(at(8, 17, 39)), Some((at(8, 17, 10), at(15, 17, 10))));
+    }
+
+    #[test]
+    fn test_day_names() {
+        let m = maintenance("days = [\"Mon\", \"sunday\"]\nstart = \"01:00\"\nend = \"03:00\"").unwrap();
+        let Schedule::Weekly { days, .. } = m.schedule else { panic!("expected a weekly schedule") };
+        assert_eq!(days, [true, false, false, false, false, false, true]);
+        for junk in ["monkey", "sundaes", "mo"] {
+            let toml = format!("days = [\"{}\"]\nstart = \"01:00\"\nend = \"03:00\"", junk);
+            assert!(maintenance(&toml).unwrap_err().contains("unknown day"), "{}", junk);
+        }
+    }
+
+    #[test]
+    fn test_skipped_result() {
+        let m = maintenance("start = \"01:00\"\nend = \"03:00\"\naction = \"skip\"").unwrap();
+        assert_eq!(m.action, MaintenanceAction::Skip);
+        let last = CheckResult::new(checks::State::Ok, "PING OK - Packet loss = 0%");
+        let result = m.skipped_result(&last);
+        assert_eq!(result.state, checks::State::Ok);
+        assert_eq!(result.output, "Maintenance 'backup', not checked: PING OK - Packet loss = 0%");
+        assert!(result.execution_end >= last.execution_end);
+        assert_eq!(maintenance("start = \"01:00\"\nend = \"03:00\"").unwrap().action, MaintenanceAction::Note);
+    }
+
+    #[test]
+    fn test_timezone() {
+        let m = maintenance("start = \"01:00\"\nend = \"03:00\"\ntimezone = \"+02:00\"").unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 0, 30, 0).unwrap();
+        let (start, end) = m.active_window(now).unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 6, 30, 23, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 7, 1, 1, 0, 0).unwrap());
+        let m = maintenance("start = \"01:00\"\nend = \"03:00\"\ntimezone = \"Europe/Berlin\"").unwrap();
+        let (start, end) = m.active_window(now).unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 6, 30, 23, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 7, 1, 1, 0, 0).unwrap());
+        assert!(maintenance("start = \"01:00\"\nend = \"03:00\"\ntimezone = \"Mars/Olympus_Mons\"")
+            .unwrap_err().contains("unsupported timezone"));
+        assert!(maintenance("start = \"01:00\"\nend = \"03:00\"\ntimezone = \"+25:00\"").is_err());
+    }
+
+    #[test]
+    fn test_to_utc_around_dst_changes() {
+        let zone = Zone::parse("Europe/Berlin").unwrap();
+        let local = |month, day, hour, minute| NaiveDate::from_ymd_opt(2024, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap();
+        let utc = |month, day, hour, minute| Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0).unwrap();
+        assert_eq!(zone.to_utc(local(7, 1, 3, 0)), utc(7, 1, 1, 0));
+        assert_eq!(zone.local_time(utc(7, 1, 1, 0)), local(7, 1, 3, 0));
+        assert_eq!(zone.to_utc(local(1, 15, 3, 0)), utc(1, 15, 2, 0));
+        // 02:30 doesn't exist on 2024-03-31, it starts at 03:30 CEST instead
+        assert_eq!(zone.to_utc(local(3, 31, 2, 30)), utc(3, 31, 1, 30));
+        // 02:30 happens twice on 2024-10-27, the first one counts
+        assert_eq!(zone.to_utc(local(10, 27, 2, 30)), utc(10, 27, 0, 30));
+    }
+
+    #[test]
+    fn test_selectors() {
+        let m = maintenance("start = \"01:00\"\nend = \"03:00\"\nchecks = [\"nas\", \"backup-*\", \"*-db-*\"]").unwrap();
+        assert!(m.matches("nas"));
+        assert!(m.matches("backup-offsite"));
+        assert!(m.matches("site2-db-primary"));
+        assert!(!m.matches("nas2"));
+        assert!(!m.matches("router"));
+        let all = maintenance("start = \"01:00\"\nend = \"03:00\"").unwrap();
+        assert!(all.matches("router"));
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+use tiny_http::{Header, Method, Request, Response, Server};
+use std::collections::BTreeMap;
+use crate::checks::{self, CheckResult, SubmissionStats};
+use crate::daemon::{Daemon, SharedDaemon};
+use crate::sinks::{self, SinkStats};
+use crate::{control, pings};
+
+const PREFIX: &str = "icinga_passive_checks";
+
+pub fn start_metrics_listener(daemon: SharedDaemon, listen: std::net::SocketAddr) -> Result<(), String> {
+    let server = Server::http(listen).map_err(|e| e.to_string())?;
+    println!("Metrics listening on {}", listen);
+
+    for request in server.incoming_requests() {
+        handle_request(request, &daemon);
+    }
+    Ok(())
+}
+
+fn handle_request(request: Request, daemon: &SharedDaemon) {
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Get, "/metrics") => {
+            (200, render(&daemon.lock().unwrap(), &checks::submission_stats(), &sinks::sink_stats()))
+        }
+        _ => (404, "not found".to_string()),
+    };
+
+    let mut response = Response::from_string(body).with_status_code(status);
+    response.add_header(Header::from_bytes("Content-Type", "text/plain; version=0.0.4; charset=utf-8").unwrap());
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send metrics response: {}", e);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn seconds_since_epoch(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default()
+}
+
+/// A metric family, written with its HELP and TYPE lines once it has samples.
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    /// Series suffix like `_sum`, labels and value
+    samples: Vec<(&'static str, String, f64)>,
+}
+
+impl Family {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Family { name, kind, help, samples: Vec::new() }
+    }
+
+    fn add(&mut self, labels: String, value: f64) {
+        self.samples.push(("", labels, value));
+    }
+
+    /// A sample of one of the family's series, like the `_sum` and `_count`
+    /// of a summary.
+    fn add_series(&mut self, suffix: &'static str, labels: String, value: f64) {
+        self.samples.push((suffix, labels, value));
+    }
+
+    fn write(&self, out: &mut String) {
+        if self.samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, self.name, self.help);
+        let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, self.name, self.kind);
+        for (suffix, labels, value) in &self.samples {
+            let _ = writeln!(out, "{}_{}{}{} {}", PREFIX, self.name, suffix, labels, value);
+        }
+    }
+}
+
+/// Render the daemon's checks and submission counters in the Prometheus
+/// text format.
+pub fn render(daemon: &Daemon, stats: &SubmissionStats, sink_stats: &BTreeMap<String, SinkStats>) -> String {
+    let mut info = Family::new("info", "gauge", "Version of the daemon.");
+    info.add(labels(&[("version", env!("CARGO_PKG_VERSION"))]), 1.0);
+    let mut uptime = Family::new("uptime_seconds", "gauge", "Seconds since the daemon started.");
+    uptime.add(String::new(), daemon.started.elapsed().as_secs_f64());
+
+    let mut state = Family::new("check_state", "gauge", "Last state of the check, 0 OK, 1 WARNING, 2 CRITICAL, 3 UNKNOWN.");
+    let mut perfdata = Family::new("check_perfdata", "gauge", "Performance data values of the last result.");
+    let mut duration = Family::new("check_duration_seconds", "gauge", "How long the last run of the check took.");
+    let mut last_run = Family::new("check_last_run_timestamp_seconds", "gauge", "When the check last ran.");
+    let mut submission_ok = Family::new("check_submission_ok", "gauge", "Whether the last result was submitted successfully.");
+    let mut last_report = Family::new("report_last_received_timestamp_seconds", "gauge", "When the last report for the check arrived.");
+    let mut overdue = Family::new("report_overdue", "gauge", "Whether a report for the check is overdue.");
+
+    let mut add_result = |check: &str, check_type: &str, result: &CheckResult| {
+        let check_labels = [("check", check), ("type", check_type)];
+        state.add(labels(&check_labels), result.state.code() as f64);
+        for perf in &result.perfdata {
+            // Undetermined values (`U`) have no sample
+            let Some(value) = perf.value else { continue };
+            let perf_labels = [("check", check), ("type", check_type), ("label", &perf.label), ("unit", &perf.unit)];
+            perfdata.add(labels(&perf_labels), value);
+        }
+    };
+    for scheduled in &daemon.pings {
+        if let Some(result) = &scheduled.last_result {
+            add_result(&scheduled.ping.name, pings::CHECK_TYPE, result);
+            // Reported results don't say how long the script took
+            if let Ok(took) = result.execution_end.duration_since(result.execution_start) {
+                let check_labels = labels(&[("check", &scheduled.ping.name), ("type", pings::CHECK_TYPE)]);
+                duration.add(check_labels, took.as_secs_f64());
+            }
+        }
+    }
+    for expected in &daemon.reports {
+        if let Some(result) = &expected.last_result {
+            add_result(&expected.report.name, control::CHECK_TYPE, result);
+        }
+    }
+
+    let submissions = daemon.pings.iter()
+        .map(|s| (s.ping.name.as_str(), pings::CHECK_TYPE, s.last_run, &s.last_submission))
+        .chain(daemon.reports.iter().map(|r| (r.report.name.as_str(), control::CHECK_TYPE, None, &r.last_submission)));
+    for (check, check_type, run, submission) in submissions {
+        let check_labels = labels(&[("check", check), ("type", check_type)]);
+        if let Some(run) = run {
+            last_run.add(check_labels.clone(), seconds_since_epoch(run));
+        }
+        if let Some(submission) = submission {
+            submission_ok.add(check_labels, if submission.is_ok() { 1.0 } else { 0.0 });
+        }
+    }
+
+    for expected in &daemon.reports {
+        let check_labels = labels(&[("check", &expected.report.name)]);
+        if let Some(received) = expected.last_report {
+            last_report.add(check_labels.clone(), seconds_since_epoch(received));
+        }
+        overdue.add(check_labels, if expected.is_overdue() { 1.0 } else { 0.0 });
+    }
+
+    let mut undelivered = Family::new("undelivered_results", "gauge", "Results Icinga doesn't have yet, held back or failed to submit.");
+    undelivered.add(String::new(), daemon.undelivered() as f64);
+
+    let mut submitted = Family::new("submissions_total", "counter", "Results submitted to a sink, once however many endpoints it took.");
+    let mut failed = Family::new("submission_failures_total", "counter", "Results a sink didn't accept.");
+    for (sink, sink_stats) in sink_stats {
+        submitted.add(labels(&[("sink", sink)]), sink_stats.submitted as f64);
+        failed.add(labels(&[("sink", sink)]), sink_stats.failed as f64);
+    }
+
+    let mut requests = Family::new("icinga_requests_total", "counter", "Requests to the Icinga API endpoints, one per endpoint tried.");
+    requests.add(String::new(), stats.sent as f64);
+    let mut request_failures = Family::new("icinga_request_failures_total", "counter", "Requests an Icinga endpoint didn't accept.");
+    request_failures.add(String::new(), stats.failed as f64);
+    let mut latency = Family::new("icinga_request_duration_seconds", "summary", "Time the Icinga endpoints took to answer.");
+    // A summary without quantiles is just its _sum and _count series
+    latency.add_series("_sum", String::new(), stats.total_latency.as_secs_f64());
+    latency.add_series("_count", String::new(), stats.sent as f64);
+
+    let mut out = String::new();
+    for family in [
+        &info, &uptime, &state, &perfdata, &duration, &last_run, &submission_ok,
+        &last_report, &overdue, &undelivered, &submitted, &failed, &requests, &request_failures, &latency,
+    ] {
+        family.write(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checks::{PerfData, State};
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(labels(&[("check", "a \"b\"\\c")]), "{check=\"a \\\"b\\\"\\\\c\"}");
+    }
+
+    #[test]
+    fn test_render() {
+        let config = crate::config::tests::minimal(r#"
+[[ping]]
+name = "router"
+host = "192.168.1.1"
+
+[[ping]]
+name = "nas"
+host = "192.168.1.20"
+
+[[report]]
+name = "zpool"
+interval = 3600
+"#);
+        let mut daemon = Daemon::new(config);
+
+        let mut result = CheckResult::new(State::Warning, "PING WARNING");
+        result.perfdata.push(PerfData::new("rtavg", 120.5, "ms"));
+        let scheduled = &mut daemon.pings[0];
+        scheduled.last_result = Some(result);
+        scheduled.last_submission = Some(Err("Icinga returned 500".to_string()));
+
+        // One result that failed over to a second endpoint
+        let stats = SubmissionStats { sent: 2, failed: 1, total_latency: std::time::Duration::from_millis(250), ..Default::default() };
+        let sink_stats = BTreeMap::from([
+            ("icinga".to_string(), SinkStats { submitted: 1, failed: 0 }),
+            ("nrdp".to_string(), SinkStats { submitted: 1, failed: 1 }),
+        ]);
+        let metrics = render(&daemon, &stats, &sink_stats);
+
+        assert!(metrics.contains("# TYPE icinga_passive_checks_check_state gauge\n"));
+        assert!(metrics.contains("icinga_passive_checks_check_state{check=\"router\",type=\"Passive Ping\"} 1\n"));
+        assert!(metrics.contains(
+            "icinga_passive_checks_check_perfdata{check=\"router\",type=\"Passive Ping\",label=\"rtavg\",unit=\"ms\"} 120.5\n"
+        ));
+        assert!(metrics.contains("icinga_passive_checks_check_submission_ok{check=\"router\",type=\"Passive Ping\"} 0\n"));
+        assert!(metrics.contains("icinga_passive_checks_report_overdue{check=\"zpool\"} 0\n"));
+        assert!(metrics.contains("icinga_passive_checks_undelivered_results 1\n"));
+        assert!(metrics.contains("icinga_passive_checks_submissions_total{sink=\"icinga\"} 1\n"));
+        assert!(metrics.contains("icinga_passive_checks_submission_failures_total{sink=\"nrdp\"} 1\n"));
+        assert!(metrics.contains("icinga_passive_checks_icinga_requests_total 2\n"));
+        assert!(metrics.contains("# TYPE icinga_passive_checks_icinga_request_duration_seconds summary\n"));
+        assert!(metrics.contains("icinga_passive_checks_icinga_request_duration_seconds_sum 0.25\n"));
+        assert!(metrics.contains("icinga_passive_checks_icinga_request_duration_seconds_count 2\n"));
+        // Checks without a result yet have no state
+        assert!(!metrics.contains("check=\"nas\""));
+    }
+}
@@ -0,0 +1,121 @@
+/// Templates for the Icinga host and service a result is submitted for,
+/// e.g. `service_name = "ping-{name}"` or `host_name = "{fqdn}"`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameTemplates {
+    pub service_name: Option<String>,
+    pub host_name: Option<String>,
+}
+
+pub const DEFAULT_SERVICE_NAME: &str = "{type}: {name}";
+pub const DEFAULT_HOST_NAME: &str = "{hostname}";
+/// Host checks are submitted for the host the check is named after
+pub const DEFAULT_CHECK_HOST_NAME: &str = "{name}";
+
+const VARIABLES: [&str; 5] = ["hostname", "fqdn", "type", "name", "host"];
+
+/// Values available to the templates.
+pub struct NameVars<'a> {
+    /// Short hostname of the machine submitting the result
+    pub hostname: &'a str,
+    /// Fully qualified name of the machine the daemon runs on, never of the
+    /// checked host
+    pub fqdn: &'a str,
+    /// Check type, e.g. `Passive Ping`
+    pub check_type: &'a str,
+    /// Check name from the config or the report
+    pub name: &'a str,
+    /// Target of the check, e.g. the pinged host
+    pub host: &'a str,
+}
+
+impl NameVars<'_> {
+    fn get(&self, variable: &str) -> Option<&str> {
+        match variable {
+            "hostname" => Some(self.hostname),
+            "fqdn" => Some(self.fqdn),
+            "type" => Some(self.check_type),
+            "name" => Some(self.name),
+            "host" => Some(self.host),
+            _ => None,
+        }
+    }
+}
+
+/// Check that a template only uses known variables and balanced braces.
+pub fn validate(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(format!("unmatched '}}' in template '{}'", template));
+        }
+        let end = rest[start..].find('}')
+            .ok_or_else(|| format!("unclosed '{{' in template '{}'", template))?;
+        let variable = &rest[start + 1..start + end];
+        if !VARIABLES.contains(&variable) {
+            return Err(format!(
+                "unknown variable '{{{}}}' in template '{}', expected one of {}",
+                variable,
+                template,
+                VARIABLES.map(|v| format!("{{{}}}", v)).join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// Fill in a template, which is expected to have passed `validate`.
+pub fn render(template: &str, vars: &NameVars) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let variable = &rest[start + 1..start + end];
+                match vars.get(variable) {
+                    Some(value) => rendered.push_str(value),
+                    None => rendered.push_str(&rest[start..start + end + 1]),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => break,
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> NameVars<'static> {
+        NameVars {
+            hostname: "server1",
+            fqdn: "server1.example.com",
+            check_type: "Passive Ping",
+            name: "router",
+            host: "192.168.1.1",
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(DEFAULT_SERVICE_NAME, &vars()), "Passive Ping: router");
+        assert_eq!(render(DEFAULT_HOST_NAME, &vars()), "server1");
+        assert_eq!(render("ping-{name}", &vars()), "ping-router");
+        assert_eq!(render("{fqdn}", &vars()), "server1.example.com");
+        assert_eq!(render("{name} via {host}", &vars()), "router via 192.168.1.1");
+        assert_eq!(render("no variables", &vars()), "no variables");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("ping-{name}").is_ok());
+        assert!(validate("{type}: {name} ({host})").is_ok());
+        assert!(validate("{nam}").unwrap_err().contains("unknown variable '{nam}'"));
+        assert!(validate("{name").unwrap_err().contains("unclosed"));
+        assert!(validate("name}").unwrap_err().contains("unmatched"));
+    }
+}
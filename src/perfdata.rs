@@ -0,0 +1,314 @@
+use std::fmt;
+use crate::threshold::Range;
+
+/// A single performance data entry,
+/// `'label'=value[UOM];[warn];[crit];[min];[max]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerfData {
+    pub label: String,
+    /// `None` for `U`, a value the plugin couldn't determine
+    pub value: Option<f64>,
+    pub unit: String,
+    pub warn: Option<Range>,
+    pub crit: Option<Range>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl PerfData {
+    pub fn new(label: &str, value: f64, unit: &str) -> Self {
+        PerfData {
+            label: label.to_string(),
+            value: Some(value),
+            unit: unit.to_string(),
+            warn: None,
+            crit: None,
+            min: None,
+            max: None,
+        }
+    }
+
+    pub fn with_min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+}
+
+impl fmt::Display for PerfData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            self.warn.map(|r| r.to_string()),
+            self.crit.map(|r| r.to_string()),
+            self.min.map(|v| v.to_string()),
+            self.max.map(|v| v.to_string()),
+        ];
+        // Trailing empty fields are left out, as the plugins do
+        let used = fields.iter().rposition(|f| f.is_some()).map_or(0, |i| i + 1);
+
+        if self.label.contains([' ', '=', '\'']) {
+            write!(f, "'{}'", self.label.replace('\'', "''"))?;
+        } else {
+            f.write_str(&self.label)?;
+        }
+        match self.value {
+            Some(value) => write!(f, "={}{}", value, self.unit)?,
+            None => f.write_str("=U")?,
+        }
+        for field in &fields[..used] {
+            write!(f, ";{}", field.as_deref().unwrap_or(""))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a list of entries the way a plugin prints them after the `|`.
+pub fn format(perfdata: &[PerfData]) -> String {
+    perfdata.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+/// Parse space separated performance data, e.g.
+/// `'free space'=12GB;5:;2:;0;100 load=0.5`.
+pub fn parse(input: &str) -> Result<Vec<PerfData>, String> {
+    let mut entries = Vec::new();
+    let mut chars = input.trim().chars().peekable();
+
+    while chars.peek().is_some() {
+        let mut label = String::new();
+        if chars.peek() == Some(&'\'') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        label.push('\'');
+                    }
+                    Some('\'') => break,
+                    Some(c) => label.push(c),
+                    None => return Err(format!("unterminated quote in label '{}'", label)),
+                }
+            }
+            if chars.peek() != Some(&'=') {
+                return Err(format!("expected '=' after label '{}'", label));
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == '=' || c.is_whitespace() {
+                    break;
+                }
+                label.push(c);
+                chars.next();
+            }
+        }
+
+        if chars.next() != Some('=') {
+            return Err(format!("missing '=' in performance data '{}'", label));
+        }
+        if label.is_empty() {
+            return Err("empty performance data label".to_string());
+        }
+
+        let mut fields = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            fields.push(c);
+            chars.next();
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        entries.push(parse_fields(&label, &fields)
+            .map_err(|e| format!("invalid performance data '{}': {}", label, e))?);
+    }
+
+    Ok(entries)
+}
+
+fn parse_fields(label: &str, fields: &str) -> Result<PerfData, String> {
+    let parts: Vec<&str> = fields.split(';').collect();
+    if parts.len() > 5 {
+        return Err("too many fields".to_string());
+    }
+
+    let value_with_unit = parts[0];
+    let (value, unit) = if value_with_unit == "U" {
+        (None, "")
+    } else {
+        let (value, unit) = parse_value(value_with_unit)?;
+        (Some(value), unit)
+    };
+
+    let field = |i: usize| parts.get(i).copied().filter(|f| !f.is_empty());
+    let range = |i: usize| field(i).map(str::parse::<Range>).transpose();
+    let number = |i: usize, name: &str| field(i)
+        .map(|f| f.parse::<f64>().map_err(|_| format!("{} '{}' is not a number", name, f)))
+        .transpose();
+
+    Ok(PerfData {
+        label: label.to_string(),
+        value,
+        unit: unit.to_string(),
+        warn: range(1)?,
+        crit: range(2)?,
+        min: number(3, "min")?,
+        max: number(4, "max")?,
+    })
+}
+
+/// Length of the number at the start of `value`: a sign, digits with an
+/// optional fraction and an exponent. `e` only starts an exponent when digits
+/// follow, so `1e-3s` is 0.001 seconds but `5e` is 5 of the unit `e`.
+fn number_len(value: &str) -> usize {
+    let bytes = value.as_bytes();
+    let digits = |from: usize| from + bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut end = digits(usize::from(matches!(bytes.first(), Some(b'+' | b'-'))));
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let exponent = end + 1 + usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent_end = digits(exponent.min(bytes.len()));
+        if exponent_end > exponent {
+            end = exponent_end;
+        }
+    }
+    end
+}
+
+fn parse_value(value_with_unit: &str) -> Result<(f64, &str), String> {
+    let (value, unit) = value_with_unit.split_at(number_len(value_with_unit));
+    if value.is_empty() {
+        return Err("missing value".to_string());
+    }
+    let value = value.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("'{}' is not a number", value))?;
+    if !unit.chars().all(|c| c.is_ascii_alphabetic() || c == '%') {
+        return Err(format!("invalid unit '{}'", unit));
+    }
+    Ok((value, unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut perf = PerfData::new("rtavg", 0.045, "ms").with_min(0.0);
+        perf.warn = Some("3000".parse().unwrap());
+        perf.crit = Some("5000".parse().unwrap());
+        assert_eq!(perf.to_string(), "rtavg=0.045ms;3000;5000;0");
+        assert_eq!(PerfData::new("users", 3.0, "").to_string(), "users=3");
+
+        let mut perf = PerfData::new("pl", 0.0, "%");
+        perf.max = Some(100.0);
+        assert_eq!(perf.to_string(), "pl=0%;;;;100");
+    }
+
+    #[test]
+    fn test_display_quotes_labels() {
+        assert_eq!(PerfData::new("free space", 1.0, "GB").to_string(), "'free space'=1GB");
+        assert_eq!(PerfData::new("a=b", 1.0, "").to_string(), "'a=b'=1");
+        assert_eq!(PerfData::new("it's", 1.0, "").to_string(), "'it''s'=1");
+    }
+
+    #[test]
+    fn test_parse_simple() {
+        let perf = parse("time=0.5s;1;2;0;10").unwrap();
+        assert_eq!(perf.len(), 1);
+        assert_eq!(perf[0].label, "time");
+        assert_eq!(perf[0].value, Some(0.5));
+        assert_eq!(perf[0].unit, "s");
+        assert_eq!(perf[0].warn, Some("1".parse().unwrap()));
+        assert_eq!(perf[0].crit, Some("2".parse().unwrap()));
+        assert_eq!(perf[0].min, Some(0.0));
+        assert_eq!(perf[0].max, Some(10.0));
+    }
+
+    #[test]
+    fn test_parse_multiple_space_separated() {
+        let perf = parse("  load1=0.5;5;10;0   load5=0.25 users=3 ").unwrap();
+        let labels: Vec<&str> = perf.iter().map(|p| p.label.as_str()).collect();
+        assert_eq!(labels, vec!["load1", "load5", "users"]);
+        assert_eq!(perf[1].warn, None);
+    }
+
+    #[test]
+    fn test_parse_quoted_labels() {
+        let perf = parse("'free space'=12GB;5:;2:;0 'it''s'=1 'a=b'=2").unwrap();
+        assert_eq!(perf[0].label, "free space");
+        assert_eq!(perf[0].unit, "GB");
+        assert_eq!(perf[0].warn, Some(Range { start: Some(5.0), end: None, inside: false }));
+        assert_eq!(perf[1].label, "it's");
+        assert_eq!(perf[2].label, "a=b");
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let perf = parse("temp=25C;@10:20;~:30").unwrap();
+        assert_eq!(perf[0].warn, Some(Range { start: Some(10.0), end: Some(20.0), inside: true }));
+        assert_eq!(perf[0].crit, Some(Range { start: None, end: Some(30.0), inside: false }));
+    }
+
+    #[test]
+    fn test_parse_empty_fields_and_negative_values() {
+        let perf = parse("offset=-0.003s;;;-1;1").unwrap();
+        assert_eq!(perf[0].value, Some(-0.003));
+        assert_eq!(perf[0].warn, None);
+        assert_eq!(perf[0].min, Some(-1.0));
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_exponents() {
+        let perf = parse("latency=1e-3s throughput=2.5E6B count=-4e+2 e=5e").unwrap();
+        assert_eq!((perf[0].value, perf[0].unit.as_str()), (Some(0.001), "s"));
+        assert_eq!((perf[1].value, perf[1].unit.as_str()), (Some(2.5e6), "B"));
+        assert_eq!((perf[2].value, perf[2].unit.as_str()), (Some(-400.0), ""));
+        // Without digits after it the `e` is the unit
+        assert_eq!((perf[3].value, perf[3].unit.as_str()), (Some(5.0), "e"));
+        assert!(parse("load=1e-").unwrap_err().contains("invalid unit"));
+    }
+
+    #[test]
+    fn test_parse_undetermined_value() {
+        let perf = parse("temp=U;50;60 load=0.5").unwrap();
+        assert_eq!(perf[0].value, None);
+        assert_eq!(perf[0].unit, "");
+        assert_eq!(perf[0].warn, Some("50".parse().unwrap()));
+        assert_eq!(perf[0].to_string(), "temp=U;50;60");
+        assert_eq!(perf[1].value, Some(0.5));
+        assert!(parse("temp=Ums").unwrap_err().contains("missing value"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "'free space'=12GB;5:;2:;0;100 rta=0.045ms;3000;5000;0 pl=0%;@80:90;~:100 'it''s'=1";
+        assert_eq!(format(&parse(input).unwrap()), input);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("load").unwrap_err().contains("missing '='"));
+        assert!(parse("=1").unwrap_err().contains("empty performance data label"));
+        assert!(parse("'free space=1").unwrap_err().contains("unterminated quote"));
+        assert!(parse("'free space' =1").unwrap_err().contains("expected '='"));
+        assert!(parse("load=").unwrap_err().contains("missing value"));
+        assert!(parse("load=abc").unwrap_err().contains("missing value"));
+        assert!(parse("load=1x2").unwrap_err().contains("invalid unit"));
+        assert!(parse("load=1;2;3;4;5;6").unwrap_err().contains("too many fields"));
+        assert!(parse("load=1;10:5").unwrap_err().contains("start is greater than end"));
+        assert!(parse("load=1;;;zero").unwrap_err().contains("min 'zero' is not a number"));
+        assert!(parse("ok=1 load=1,2").unwrap_err().contains("invalid performance data 'load'"));
+    }
+
+    #[test]
+    fn test_comma_is_not_a_separator() {
+        // The old parser split on commas, which the spec doesn't allow
+        assert!(parse("a=1,b=2").is_err());
+    }
+}
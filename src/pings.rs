@@ -1,8 +1,9 @@
+use std::collections::BTreeMap;
 use std::process::Command;
+use std::time::SystemTime;
 use regex::Regex;
-use std::collections::HashMap;
-use crate::checks::{CheckResult, send_passive_check};
-use crate::config::IcingaConfig;
+use crate::checks::{CheckResult, PerfData, State};
+use crate::threshold::Thresholds;
 
 #[derive(Debug)]
 struct PingMetrics {
@@ -14,49 +15,89 @@ struct PingMetrics {
     rtt_mdev: f64,
 }
 
-pub fn ping_host(check_source: &str, check_name: &str, check_host: &str, icinga_config: &IcingaConfig) {
-    let ping_data = execute_ping(check_host);
-    send_passive_check(
-        check_source,
-        check_name,
-        check_host,
-        "Passive Ping",
-        &ping_data,
-        icinga_config,
-    );
+pub const CHECK_TYPE: &str = "Passive Ping";
+
+/// Labels of the performance data a ping reports, thresholds can be
+/// configured for each of them.
+pub const PERFDATA_LABELS: [&str; 6] = ["rtavg", "rtmin", "rtmax", "rtdev", "pl", "time"];
+
+/// The warn/crit fields reported for labels that have no thresholds
+/// configured. They are only informational, without configured thresholds
+/// the state depends on packet loss alone.
+fn default_perfdata_thresholds(label: &str) -> Thresholds {
+    let (warn, crit) = match label {
+        "pl" => ("80", "100"),
+        "time" => ("8500", "10000"),
+        _ => ("3000", "5000"),
+    };
+    Thresholds::new(Some(warn), Some(crit)).unwrap()
+}
+
+/// Roughly how long one ping takes: eight packets a second apart, a little
+/// longer when they get lost. Pings run one after another, so this bounds
+/// how often each of them can run.
+pub const EXPECTED_DURATION: u64 = 10;
+
+/// The ping command line, also reported to Icinga as the check command.
+pub fn command_line(host: &str) -> Vec<String> {
+    ["ping", "-c", "8", host].iter().map(|s| s.to_string()).collect()
+}
+
+pub fn execute_ping(host: &str, thresholds: &BTreeMap<String, Thresholds>) -> CheckResult {
+    run_ping(&command_line(host), thresholds)
 }
 
-fn execute_ping(host: &str) -> CheckResult {
-    let output = Command::new("ping")
-        .arg("-c")
-        .arg("8")
-        .arg(host)
-        .output()
-        .expect("Failed to execute ping command");
+fn run_ping(command_line: &[String], thresholds: &BTreeMap<String, Thresholds>) -> CheckResult {
+    let execution_start = SystemTime::now();
+    let output = match Command::new(&command_line[0]).args(&command_line[1..]).output() {
+        Ok(output) => output,
+        // Runs requested over the socket or HTTP happen on their threads,
+        // a missing ping binary mustn't take the daemon down with them
+        Err(e) => {
+            let mut result = CheckResult::new(State::Unknown, &format!("PING UNKNOWN - Failed to run {}: {}", command_line[0], e));
+            result.execution_start = execution_start;
+            return result;
+        }
+    };
     let response = String::from_utf8_lossy(&output.stdout);
     let metrics = parse_ping_metrics(&response);
-    format_ping_result(&metrics)
+    let mut result = format_ping_result(&metrics, thresholds);
+    result.execution_start = execution_start;
+    result
 }
 
-fn format_ping_result(metrics: &PingMetrics) -> CheckResult {
-    let mut result = HashMap::new();
-    let status = if metrics.packet_loss == 0.0 { "OK" } else { "CRITICAL" };
-    
-    result.insert("exit_status".to_string(), 
-        if metrics.packet_loss == 0.0 { "0" } else { "2" }.to_string());
-    result.insert("plugin_output".to_string(), 
-        format!("PING {} - Packet loss = {}% AVG = {}ms", 
-            status, metrics.packet_loss, metrics.rtt_avg));
-    result.insert("performance_data".to_string(), 
-        vec![
-            format!("rtavg={}ms;3000;5000;0", metrics.rtt_avg),
-            format!("rtmin={}ms;3000;5000;0", metrics.rtt_min),
-            format!("rtmax={}ms;3000;5000;0", metrics.rtt_max),
-            format!("rtdev={}ms;3000;5000;0", metrics.rtt_mdev),
-            format!("pl={}%;80;100;0", metrics.packet_loss),
-            format!("time={}ms;8500;10000;0", metrics.time),
-        ].join(","));
-    
+fn format_ping_result(metrics: &PingMetrics, thresholds: &BTreeMap<String, Thresholds>) -> CheckResult {
+    let mut perfdata = vec![
+        PerfData::new("rtavg", metrics.rtt_avg, "ms").with_min(0.0),
+        PerfData::new("rtmin", metrics.rtt_min, "ms").with_min(0.0),
+        PerfData::new("rtmax", metrics.rtt_max, "ms").with_min(0.0),
+        PerfData::new("rtdev", metrics.rtt_mdev, "ms").with_min(0.0),
+        PerfData::new("pl", metrics.packet_loss, "%").with_min(0.0),
+        PerfData::new("time", metrics.time as f64, "ms").with_min(0.0),
+    ];
+
+    // Any packet loss is critical unless `pl` has thresholds
+    let mut state = if thresholds.contains_key("pl") || metrics.packet_loss == 0.0 {
+        State::Ok
+    } else {
+        State::Critical
+    };
+    for perf in &mut perfdata {
+        match thresholds.get(&perf.label) {
+            Some(thresholds) => state = state.worst(thresholds.apply(perf)),
+            None => {
+                let defaults = default_perfdata_thresholds(&perf.label);
+                perf.warn = defaults.warn;
+                perf.crit = defaults.crit;
+            }
+        }
+    }
+
+    let mut result = CheckResult::new(state, &format!(
+        "PING {} - Packet loss = {}% AVG = {}ms",
+        state, metrics.packet_loss, metrics.rtt_avg));
+    result.perfdata = perfdata;
+
     result
 }
 
@@ -131,6 +172,14 @@ rtt min/avg/max/mdev = 4.752/4.812/4.876/0.047 ms"#.to_string()
         "Invalid ping output".to_string()
     }
 
+    #[test]
+    fn test_missing_ping_binary() {
+        let command_line = ["/nonexistent/ping".to_string(), "192.168.1.1".to_string()];
+        let result = run_ping(&command_line, &BTreeMap::new());
+        assert_eq!(result.state, State::Unknown);
+        assert!(result.output.starts_with("PING UNKNOWN - Failed to run /nonexistent/ping: "), "{}", result.output);
+    }
+
     #[test]
     fn test_parse_ping_metrics() {
         let metrics = parse_ping_metrics(&mock_ping_success());
@@ -140,18 +189,56 @@ rtt min/avg/max/mdev = 4.752/4.812/4.876/0.047 ms"#.to_string()
 
     #[test]
     fn test_ping_success() {
-        let result = format_ping_result(&parse_ping_metrics(&mock_ping_success()));
-        assert_eq!(result.get("exit_status").unwrap(), "0");
-        assert!(result.get("plugin_output").unwrap().contains("PING OK"));
-        assert!(result.get("performance_data").unwrap().contains("pl=0%"));
+        let result = format_ping_result(&parse_ping_metrics(&mock_ping_success()), &BTreeMap::new());
+        assert_eq!(result.state, State::Ok);
+        assert!(result.output.contains("PING OK"));
+        assert!(result.perfdata.iter().any(|p| p.to_string() == "pl=0%;80;100;0"));
+        assert!(result.perfdata.iter().any(|p| p.to_string() == "rtavg=4.812ms;3000;5000;0"));
+    }
+
+    #[test]
+    fn test_ping_default_output() {
+        // Without configured thresholds only packet loss decides the state,
+        // even for round trip times above the reported thresholds
+        let mut metrics = parse_ping_metrics(&mock_ping_success());
+        metrics.rtt_avg = 6000.0;
+        metrics.time = 12000;
+        let result = format_ping_result(&metrics, &BTreeMap::new());
+        assert_eq!(result.state, State::Ok);
+        assert_eq!(result.output, "PING OK - Packet loss = 0% AVG = 6000ms");
+        assert_eq!(
+            crate::perfdata::format(&result.perfdata),
+            "rtavg=6000ms;3000;5000;0 rtmin=4.752ms;3000;5000;0 rtmax=4.876ms;3000;5000;0 \
+             rtdev=0.047ms;3000;5000;0 pl=0%;80;100;0 time=12000ms;8500;10000;0"
+        );
+
+        let result = format_ping_result(&parse_ping_metrics(&mock_ping_partial_loss()), &BTreeMap::new());
+        assert_eq!(result.state, State::Critical);
+        assert_eq!(result.output, "PING CRITICAL - Packet loss = 50% AVG = 4.812ms");
     }
 
     #[test]
     fn test_ping_failure() {
-        let result = format_ping_result(&parse_ping_metrics(&mock_ping_failure()));
-        assert_eq!(result.get("exit_status").unwrap(), "2");
-        assert!(result.get("plugin_output").unwrap().contains("PING CRITICAL"));
-        assert!(result.get("performance_data").unwrap().contains("pl=100%"));
+        let result = format_ping_result(&parse_ping_metrics(&mock_ping_failure()), &BTreeMap::new());
+        assert_eq!(result.state, State::Critical);
+        assert!(result.output.contains("PING CRITICAL"));
+        assert!(result.perfdata.iter().any(|p| p.to_string() == "pl=100%;80;100;0"));
+    }
+
+    #[test]
+    fn test_ping_configured_thresholds() {
+        let thresholds = BTreeMap::from([
+            ("pl".to_string(), Thresholds::new(Some("20"), Some("60")).unwrap()),
+            ("rtavg".to_string(), Thresholds::new(Some("2"), Some("10")).unwrap()),
+        ]);
+        let result = format_ping_result(&parse_ping_metrics(&mock_ping_success()), &thresholds);
+        assert_eq!(result.state, State::Warning);
+        assert!(result.output.contains("PING WARNING"));
+        assert!(result.perfdata.iter().any(|p| p.to_string() == "rtavg=4.812ms;2;10;0"));
+
+        let result = format_ping_result(&parse_ping_metrics(&mock_ping_partial_loss()), &thresholds);
+        assert_eq!(result.state, State::Warning);
+        assert!(result.perfdata.iter().any(|p| p.to_string() == "pl=50%;20;60;0"));
     }
 
     #[test]
@@ -0,0 +1,459 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use crate::checks::{self, CheckResult, IcingaObject, SubmitOptions};
+use crate::config::IcingaConfig;
+use crate::perfdata;
+
+/// Name of the built-in sink submitting to the Icinga2 API from `[icinga]`.
+pub const ICINGA_SINK: &str = "icinga";
+
+/// Payload format of an NRDP submission.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NrdpFormat {
+    #[default]
+    Xml,
+    Json,
+}
+
+/// Where check results are delivered.
+#[derive(Debug, Clone)]
+pub enum Sink {
+    /// The Icinga2 REST API configured in `[icinga]`
+    Icinga,
+    /// A Nagios NRDP server. It has its own client, the TLS settings of
+    /// `[icinga]` (CA, client certificate, pinning) are only for Icinga.
+    Nrdp { url: String, token: String, format: NrdpFormat, client: reqwest::blocking::Client },
+    /// An NSCA-ng server, through its `send_nsca` client, which does the
+    /// TLS-PSK handshake and reads the identity and password from its config
+    NscaNg { command: String, server: Option<String>, port: Option<u16>, config: Option<PathBuf> },
+    /// One JSON object per line appended to a file
+    File { path: PathBuf },
+}
+
+/// A `[[sink]]` from the config, or the built-in `icinga` one.
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    pub name: String,
+    pub sink: Sink,
+}
+
+/// Results submitted to a sink since the daemon started, each counted once
+/// however many Icinga endpoints it took.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SinkStats {
+    pub submitted: u64,
+    pub failed: u64,
+}
+
+static SINK_STATS: Mutex<BTreeMap<String, SinkStats>> = Mutex::new(BTreeMap::new());
+
+pub fn sink_stats() -> BTreeMap<String, SinkStats> {
+    SINK_STATS.lock().unwrap().clone()
+}
+
+fn record_delivery(sink: &str, success: bool) {
+    let mut stats = SINK_STATS.lock().unwrap();
+    let stats = stats.entry(sink.to_string()).or_default();
+    stats.submitted += 1;
+    if !success {
+        stats.failed += 1;
+    }
+}
+
+/// Submit a result to every sink of the check, the check's own `sinks` or
+/// the global default. All sinks are tried even if one fails.
+pub fn submit(
+    check_source: &str,
+    check_name: &str,
+    check_host: &str,
+    check_type: &str,
+    check_data: &CheckResult,
+    options: &SubmitOptions,
+    icinga_config: &IcingaConfig,
+) -> Result<(), String> {
+    let names = options.sinks.as_ref().unwrap_or(&icinga_config.default_sinks);
+    let mut errors = Vec::new();
+    for name in names {
+        let Some(sink) = icinga_config.sinks.iter().find(|sink| sink.name == *name) else {
+            errors.push(format!("{}: unknown sink", name));
+            continue;
+        };
+        let object = || checks::check_object(
+            check_source, check_name, check_host, check_type, options, &icinga_config.names,
+        );
+        let result = match &sink.sink {
+            Sink::Icinga => checks::send_passive_check(
+                check_source, check_name, check_host, check_type, check_data, options, icinga_config,
+            ),
+            Sink::Nrdp { url, token, format, client } => {
+                send_nrdp(client, url, token, *format, &object(), check_data)
+            }
+            Sink::NscaNg { command, server, port, config } => {
+                let args = nsca_args(server.as_deref(), *port, config.as_deref());
+                send_nsca(command, &args, &nsca_record(&object(), check_data))
+            }
+            Sink::File { path } => write_file(path, &object(), check_source, check_data),
+        };
+        record_delivery(&sink.name, result.is_ok());
+        // The Icinga sink logs its own outcome
+        if !matches!(sink.sink, Sink::Icinga) {
+            match &result {
+                Ok(()) => println!(
+                    "Successfully sent passive check result for host {} check {} to {}",
+                    check_source, check_name, sink.name
+                ),
+                Err(e) => println!(
+                    "Failed to send passive check result for host {} check {} to {}: {}",
+                    check_source, check_name, sink.name, e
+                ),
+            }
+        }
+        if let Err(e) = result {
+            errors.push(if names.len() > 1 { format!("{}: {}", name, e) } else { e });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// Output in the plugin format, `output|perfdata` followed by the long output.
+/// A `|` in the text would start the perfdata early, it becomes `¦`.
+fn nagios_output(check_data: &CheckResult) -> String {
+    let text = |text: &str| text.replace('|', "¦");
+    let mut output = text(&check_data.output);
+    if !check_data.perfdata.is_empty() {
+        output = format!("{}|{}", output, perfdata::format(&check_data.perfdata));
+    }
+    match &check_data.long_output {
+        Some(long_output) => format!("{}\n{}", output, text(long_output)),
+        None => output,
+    }
+}
+
+fn state_code(object: &IcingaObject, check_data: &CheckResult) -> i32 {
+    match object.service {
+        Some(_) => check_data.state.code(),
+        None => check_data.state.host_code(),
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn nrdp_xml(object: &IcingaObject, check_data: &CheckResult) -> String {
+    let (kind, service) = match &object.service {
+        Some(service) => ("service", format!("<servicename>{}</servicename>", escape_xml(service))),
+        None => ("host", String::new()),
+    };
+    format!(
+        "<?xml version='1.0'?><checkresults><checkresult type='{}' checktype='1'>\
+         <hostname>{}</hostname>{}<state>{}</state><output>{}</output>\
+         </checkresult></checkresults>",
+        kind,
+        escape_xml(&object.host),
+        service,
+        state_code(object, check_data),
+        escape_xml(&nagios_output(check_data)),
+    )
+}
+
+fn nrdp_json(object: &IcingaObject, check_data: &CheckResult) -> String {
+    let mut result = json!({
+        "checkresult": {
+            "type": if object.service.is_some() { "service" } else { "host" },
+            "checktype": "1",
+        },
+        "hostname": object.host,
+        "state": state_code(object, check_data).to_string(),
+        "output": nagios_output(check_data),
+    });
+    if let Some(service) = &object.service {
+        result["servicename"] = Value::from(service.as_str());
+    }
+    json!({ "checkresults": [result] }).to_string()
+}
+
+/// NRDP answers 200 even when it rejects a submission, the outcome is in
+/// the body as `<status>0</status>` or `{"result": {"status": 0}}`.
+fn nrdp_error(body: &str) -> Option<String> {
+    if let Ok(json) = serde_json::from_str::<Value>(body) {
+        let result = &json["result"];
+        return match result["status"].as_i64().or_else(|| result["status"].as_str()?.parse().ok()) {
+            Some(0) => None,
+            _ => Some(result["message"].as_str().unwrap_or(body).to_string()),
+        };
+    }
+    if body.contains("<status>0</status>") {
+        return None;
+    }
+    let message = body.split("<message>").nth(1)
+        .and_then(|rest| rest.split("</message>").next())
+        .unwrap_or(body);
+    Some(message.trim().to_string())
+}
+
+fn send_nrdp(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    token: &str,
+    format: NrdpFormat,
+    object: &IcingaObject,
+    check_data: &CheckResult,
+) -> Result<(), String> {
+    let (field, data) = match format {
+        NrdpFormat::Xml => ("XMLDATA", nrdp_xml(object, check_data)),
+        NrdpFormat::Json => ("JSONDATA", nrdp_json(object, check_data)),
+    };
+    let response = client.post(url)
+        .form(&[("token", token), ("cmd", "submitcheck"), (field, &data)])
+        .send()
+        .map_err(|e| format!("request failed: {}", e))?;
+
+    let status = response.status();
+    let body = response.text().unwrap_or_default();
+    if status != StatusCode::OK {
+        return Err(format!("NRDP returned {}", status));
+    }
+    match nrdp_error(&body) {
+        None => Ok(()),
+        Some(message) => Err(format!("NRDP rejected the result: {}", message)),
+    }
+}
+
+/// Check results are separated by ETB, so the output may span lines
+const NSCA_SEPARATOR: char = '\x17';
+
+fn nsca_args(server: Option<&str>, port: Option<u16>, config: Option<&Path>) -> Vec<String> {
+    let mut args = vec!["-e".to_string(), NSCA_SEPARATOR.to_string()];
+    if let Some(server) = server {
+        args.extend(["-H".to_string(), server.to_string()]);
+    }
+    if let Some(port) = port {
+        args.extend(["-p".to_string(), port.to_string()]);
+    }
+    if let Some(config) = config {
+        args.extend(["-c".to_string(), config.display().to_string()]);
+    }
+    args
+}
+
+/// A tab separated `host[\tservice]\tstate\toutput` record, tabs within the
+/// fields would split them and are replaced.
+fn nsca_record(object: &IcingaObject, check_data: &CheckResult) -> String {
+    let field = |value: &str| value.replace(['\t', NSCA_SEPARATOR], " ");
+    let mut fields = vec![field(&object.host)];
+    fields.extend(object.service.as_deref().map(field));
+    fields.push(state_code(object, check_data).to_string());
+    fields.push(field(&nagios_output(check_data)));
+    format!("{}{}", fields.join("\t"), NSCA_SEPARATOR)
+}
+
+fn send_nsca(command: &str, args: &[String], record: &str) -> Result<(), String> {
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(record.as_bytes())
+            .map_err(|e| format!("Failed to write to {}: {}", command, e))?;
+    }
+    let output = child.wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if stderr.is_empty() {
+        Err(format!("{} failed with {}", command, output.status))
+    } else {
+        Err(format!("{} failed with {}: {}", command, output.status, stderr))
+    }
+}
+
+fn write_file(path: &Path, object: &IcingaObject, check_source: &str, check_data: &CheckResult) -> Result<(), String> {
+    let line = json!({
+        "timestamp": checks::unix_time(SystemTime::now()),
+        "host": object.host,
+        "service": object.service,
+        "check_source": check_source,
+        "state": state_code(object, check_data),
+        "state_name": check_data.state.as_str(),
+        "output": check_data.output,
+        "long_output": check_data.long_output,
+        "performance_data": check_data.perfdata.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+        "execution_start": checks::unix_time(check_data.execution_start),
+        "execution_end": checks::unix_time(check_data.execution_end),
+    });
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checks::{PerfData, State};
+
+    fn service() -> IcingaObject {
+        IcingaObject { host: "server1".to_string(), service: Some("Passive Ping: <lo>".to_string()) }
+    }
+
+    fn result() -> CheckResult {
+        let mut result = CheckResult::new(State::Warning, "PING WARNING & slow");
+        result.perfdata.push(PerfData::new("rta", 12.5, "ms"));
+        result
+    }
+
+    #[test]
+    fn test_nrdp_xml() {
+        assert_eq!(
+            nrdp_xml(&service(), &result()),
+            "<?xml version='1.0'?><checkresults><checkresult type='service' checktype='1'>\
+             <hostname>server1</hostname><servicename>Passive Ping: &lt;lo&gt;</servicename>\
+             <state>1</state><output>PING WARNING &amp; slow|rta=12.5ms</output>\
+             </checkresult></checkresults>"
+        );
+
+        let host = IcingaObject { host: "nas".to_string(), service: None };
+        let xml = nrdp_xml(&host, &CheckResult::new(State::Critical, "DOWN"));
+        assert!(xml.contains("type='host'"));
+        assert!(xml.contains("<state>1</state>"));
+        assert!(!xml.contains("servicename"));
+    }
+
+    #[test]
+    fn test_nrdp_json() {
+        let json: Value = serde_json::from_str(&nrdp_json(&service(), &result())).unwrap();
+        let result = &json["checkresults"][0];
+        assert_eq!(result["checkresult"]["type"], "service");
+        assert_eq!(result["servicename"], "Passive Ping: <lo>");
+        assert_eq!(result["state"], "1");
+        assert_eq!(result["output"], "PING WARNING & slow|rta=12.5ms");
+    }
+
+    #[test]
+    fn test_nagios_output_escapes_pipes() {
+        let mut result = result();
+        result.output = "backup a|b done".to_string();
+        result.long_output = Some("copied x|y".to_string());
+        assert_eq!(nagios_output(&result), "backup a¦b done|rta=12.5ms\ncopied x¦y");
+    }
+
+    #[test]
+    fn test_nrdp_error() {
+        assert_eq!(nrdp_error("<result><status>0</status><message>OK</message></result>"), None);
+        assert_eq!(
+            nrdp_error("<result><status>-1</status><message>BAD TOKEN</message></result>"),
+            Some("BAD TOKEN".to_string())
+        );
+        assert_eq!(nrdp_error(r#"{"result": {"status": 0, "message": "OK"}}"#), None);
+        assert_eq!(
+            nrdp_error(r#"{"result": {"status": "-1", "message": "NO DATA"}}"#),
+            Some("NO DATA".to_string())
+        );
+    }
+
+    #[test]
+    fn test_send_nrdp_to_stand_in_server() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/nrdp/", server.server_addr().to_ip().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for answer in ["<result><status>0</status><message>OK</message></result>",
+                           "<result><status>-1</status><message>BAD TOKEN</message></result>"] {
+                let mut request = server.recv().unwrap();
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                bodies.push(body);
+                request.respond(tiny_http::Response::from_string(answer)).unwrap();
+            }
+            bodies
+        });
+
+        let client = reqwest::blocking::Client::new();
+        assert_eq!(send_nrdp(&client, &url, "s3cret", NrdpFormat::Xml, &service(), &result()), Ok(()));
+        let err = send_nrdp(&client, &url, "wrong", NrdpFormat::Json, &service(), &result()).unwrap_err();
+        assert_eq!(err, "NRDP rejected the result: BAD TOKEN");
+
+        let bodies = handle.join().unwrap();
+        assert!(bodies[0].starts_with("token=s3cret&cmd=submitcheck&XMLDATA=%3C%3Fxml"), "{}", bodies[0]);
+        assert!(bodies[1].contains("&JSONDATA=%7B"), "{}", bodies[1]);
+    }
+
+    #[test]
+    fn test_nsca_record() {
+        let mut check_data = result();
+        check_data.output = "PING WARNING\tslow".to_string();
+        check_data.long_output = Some("line 2".to_string());
+        assert_eq!(
+            nsca_record(&service(), &check_data),
+            "server1\tPassive Ping: <lo>\t1\tPING WARNING slow|rta=12.5ms\nline 2\x17"
+        );
+        let host = IcingaObject { host: "nas".to_string(), service: None };
+        assert_eq!(nsca_record(&host, &CheckResult::new(State::Critical, "DOWN")), "nas\t1\tDOWN\x17");
+    }
+
+    #[test]
+    fn test_nsca_args() {
+        assert_eq!(nsca_args(None, None, None), vec!["-e", "\x17"]);
+        assert_eq!(
+            nsca_args(Some("nagios.site3"), Some(5668), Some(Path::new("/etc/send_nsca.cfg"))),
+            vec!["-e", "\x17", "-H", "nagios.site3", "-p", "5668", "-c", "/etc/send_nsca.cfg"]
+        );
+    }
+
+    #[test]
+    fn test_send_nsca_with_stand_in_client() {
+        let path = std::env::temp_dir().join(format!("icinga_passive_checks_nsca_{}", std::process::id()));
+        let script = format!("cat > {}", path.display());
+        let record = nsca_record(&service(), &result());
+        assert_eq!(send_nsca("sh", &["-c".to_string(), script], &record), Ok(()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), record);
+        std::fs::remove_file(&path).unwrap();
+
+        let err = send_nsca("sh", &["-c".to_string(), "echo 'connection refused' >&2; exit 2".to_string()], &record)
+            .unwrap_err();
+        assert_eq!(err, "sh failed with exit status: 2: connection refused");
+        assert!(send_nsca("/nonexistent/send_nsca", &[], &record).unwrap_err().starts_with("Failed to run"));
+    }
+
+    #[test]
+    fn test_write_file() {
+        let path = std::env::temp_dir().join(format!("icinga_passive_checks_sink_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        write_file(&path, &service(), "server1", &result()).unwrap();
+        write_file(&path, &service(), "server1", &CheckResult::new(State::Ok, "PING OK")).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["service"], "Passive Ping: <lo>");
+        assert_eq!(lines[0]["state"], 1);
+        assert_eq!(lines[0]["state_name"], "WARNING");
+        assert_eq!(lines[0]["performance_data"], json!(["rta=12.5ms"]));
+        assert_eq!(lines[1]["output"], "PING OK");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -11,6 +11,7 @@ pub fn generate_unit_content(description: &str, exec_start: &str) -> String {
 
     sections.insert("Service", vec![
         ("ExecStart", format!("{} --daemon", exec_start)),
+        ("ExecReload", "/bin/kill -HUP $MAINPID".to_string()),
         ("DynamicUser", "true".to_string()),
         ("NoNewPrivileges", "true".to_string()),
         ("ProtectSystem", "strict".to_string()),
@@ -20,6 +21,8 @@ pub fn generate_unit_content(description: &str, exec_start: &str) -> String {
         ("RestrictSUIDSGID", "true".to_string()),
         ("RestrictNamespaces", "true".to_string()),
         ("RuntimeDirectory", "icinga_passive_checks".to_string()),
+        // Uncomment to hand the API password to the service as a credential
+        ("#LoadCredential", "api_password:/etc/icinga_passive_checks/api_password".to_string()),
     ]);
 
     sections.insert("Install", vec![
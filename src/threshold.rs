@@ -0,0 +1,236 @@
+use std::fmt;
+use std::str::FromStr;
+use toml::Value;
+use crate::checks::{PerfData, State};
+
+/// A Nagios threshold range, `[@][start:][end]`.
+///
+/// `start` of `None` is negative infinity (`~`), `end` of `None` is positive
+/// infinity. With `inside` set (`@`) a value alerts when it is within the
+/// range, otherwise when it is outside of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub inside: bool,
+}
+
+fn parse_bound(bound: &str, range: &str) -> Result<f64, String> {
+    bound.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("invalid range '{}': '{}' is not a number", range, bound))
+}
+
+impl Range {
+    /// Whether `value` should raise an alert for this range.
+    pub fn alerts(&self, value: f64) -> bool {
+        let within = self.start.is_none_or(|start| value >= start)
+            && self.end.is_none_or(|end| value <= end);
+        within == self.inside
+    }
+}
+
+impl FromStr for Range {
+    type Err = String;
+
+    fn from_str(range: &str) -> Result<Self, Self::Err> {
+        let (inside, rest) = match range.strip_prefix('@') {
+            Some(rest) => (true, rest),
+            None => (false, range),
+        };
+        if rest.is_empty() {
+            return Err(format!("invalid range '{}': empty range", range));
+        }
+
+        let (start, end) = match rest.split_once(':') {
+            Some((start, end)) => {
+                let start = match start {
+                    "~" => None,
+                    "" => Some(0.0),
+                    start => Some(parse_bound(start, range)?),
+                };
+                let end = match end {
+                    "" => None,
+                    end => Some(parse_bound(end, range)?),
+                };
+                (start, end)
+            }
+            None => (Some(0.0), Some(parse_bound(rest, range)?)),
+        };
+
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Err(format!("invalid range '{}': start is greater than end", range));
+            }
+        }
+
+        Ok(Range { start, end, inside })
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.inside {
+            f.write_str("@")?;
+        }
+        match (self.start, self.end) {
+            (Some(0.0), Some(end)) => write!(f, "{}", end),
+            (Some(start), Some(end)) => write!(f, "{}:{}", start, end),
+            (Some(start), None) => write!(f, "{}:", start),
+            (None, Some(end)) => write!(f, "~:{}", end),
+            (None, None) => f.write_str("~:"),
+        }
+    }
+}
+
+/// Warning and critical ranges for a single value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Thresholds {
+    pub warn: Option<Range>,
+    pub crit: Option<Range>,
+}
+
+impl Thresholds {
+    pub fn new(warn: Option<&str>, crit: Option<&str>) -> Result<Self, String> {
+        Ok(Thresholds {
+            warn: warn.map(str::parse).transpose()?,
+            crit: crit.map(str::parse).transpose()?,
+        })
+    }
+
+    /// Read `{ warn = "...", crit = "..." }` from the config, plain numbers
+    /// are accepted as well as range strings.
+    pub fn from_toml(value: &Value) -> Result<Self, String> {
+        let table = value.as_table().ok_or("thresholds must be a table with 'warn' and/or 'crit'")?;
+        if let Some(key) = table.keys().find(|k| *k != "warn" && *k != "crit") {
+            return Err(format!("unknown threshold key '{}'", key));
+        }
+        let range = |key: &str| -> Result<Option<String>, String> {
+            match table.get(key) {
+                Some(Value::String(s)) => Ok(Some(s.clone())),
+                Some(Value::Integer(i)) => Ok(Some(i.to_string())),
+                Some(Value::Float(f)) => Ok(Some(f.to_string())),
+                Some(_) => Err(format!("'{}' must be a number or a range string", key)),
+                None => Ok(None),
+            }
+        };
+        Thresholds::new(range("warn")?.as_deref(), range("crit")?.as_deref())
+    }
+
+    pub fn state(&self, value: f64) -> State {
+        if self.crit.is_some_and(|r| r.alerts(value)) {
+            State::Critical
+        } else if self.warn.is_some_and(|r| r.alerts(value)) {
+            State::Warning
+        } else {
+            State::Ok
+        }
+    }
+
+    /// Copy the thresholds into the perfdata entry and return its state, so
+    /// the reported warn/crit fields always match how the state was derived.
+    /// An undetermined value is UNKNOWN.
+    pub fn apply(&self, perf: &mut PerfData) -> State {
+        perf.warn = self.warn;
+        perf.crit = self.crit;
+        perf.value.map_or(State::Unknown, |value| self.state(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> Range {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(range("10"), Range { start: Some(0.0), end: Some(10.0), inside: false });
+        assert_eq!(range("10:"), Range { start: Some(10.0), end: None, inside: false });
+        assert_eq!(range("~:10"), Range { start: None, end: Some(10.0), inside: false });
+        assert_eq!(range("10:20"), Range { start: Some(10.0), end: Some(20.0), inside: false });
+        assert_eq!(range("@10:20"), Range { start: Some(10.0), end: Some(20.0), inside: true });
+        assert_eq!(range("-5.5:-1"), Range { start: Some(-5.5), end: Some(-1.0), inside: false });
+    }
+
+    #[test]
+    fn test_parse_invalid_range() {
+        assert!("".parse::<Range>().is_err());
+        assert!("@".parse::<Range>().is_err());
+        assert!("abc".parse::<Range>().is_err());
+        assert!("10:5".parse::<Range>().unwrap_err().contains("start is greater than end"));
+        assert!("1:2:3".parse::<Range>().is_err());
+        assert!("inf".parse::<Range>().is_err());
+    }
+
+    #[test]
+    fn test_alerts() {
+        // 10 => alert outside 0..10
+        assert!(range("10").alerts(-1.0));
+        assert!(!range("10").alerts(0.0));
+        assert!(!range("10").alerts(10.0));
+        assert!(range("10").alerts(10.1));
+        // 10: => alert below 10
+        assert!(range("10:").alerts(9.9));
+        assert!(!range("10:").alerts(1e9));
+        // ~:10 => alert above 10
+        assert!(!range("~:10").alerts(-1e9));
+        assert!(range("~:10").alerts(11.0));
+        // 10:20 => alert outside 10..20
+        assert!(range("10:20").alerts(9.0));
+        assert!(!range("10:20").alerts(15.0));
+        assert!(range("10:20").alerts(21.0));
+        // @10:20 => alert inside 10..20
+        assert!(!range("@10:20").alerts(9.0));
+        assert!(range("@10:20").alerts(10.0));
+        assert!(range("@10:20").alerts(20.0));
+        assert!(!range("@10:20").alerts(21.0));
+        // 0 => alert on anything but zero
+        assert!(!range("0").alerts(0.0));
+        assert!(range("0").alerts(12.5));
+    }
+
+    #[test]
+    fn test_thresholds_state() {
+        let thresholds = Thresholds::new(Some("100"), Some("500")).unwrap();
+        assert_eq!(thresholds.state(50.0), State::Ok);
+        assert_eq!(thresholds.state(150.0), State::Warning);
+        assert_eq!(thresholds.state(501.0), State::Critical);
+        assert_eq!(Thresholds::default().state(1e9), State::Ok);
+
+        let only_crit = Thresholds::new(None, Some("@0:10")).unwrap();
+        assert_eq!(only_crit.state(5.0), State::Critical);
+        assert_eq!(only_crit.state(50.0), State::Ok);
+    }
+
+    #[test]
+    fn test_thresholds_apply() {
+        let thresholds = Thresholds::new(Some("80"), Some("90")).unwrap();
+        let mut perf = PerfData::new("used", 85.0, "%");
+        assert_eq!(thresholds.apply(&mut perf), State::Warning);
+        assert_eq!(perf.to_string(), "used=85%;80;90");
+    }
+
+    #[test]
+    fn test_thresholds_from_toml() {
+        let value: Value = toml::from_str("warn = 100\ncrit = \"@0.5:1\"").unwrap();
+        let thresholds = Thresholds::from_toml(&value).unwrap();
+        assert_eq!(thresholds.warn, Some(range("100")));
+        assert_eq!(thresholds.crit, Some(range("@0.5:1")));
+
+        let value: Value = toml::from_str("warning = 100").unwrap();
+        assert!(Thresholds::from_toml(&value).unwrap_err().contains("unknown threshold key"));
+        let value: Value = toml::from_str("crit = \"5:1\"").unwrap();
+        assert!(Thresholds::from_toml(&value).is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for s in ["10", "10:", "~:10", "10:20", "@10:20", "@~:0.5", "-1:1"] {
+            assert_eq!(range(s).to_string(), s);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{verify_server_name, WebPkiServerVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::ParsedCertificate;
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+/// TLS settings for the connection to the Icinga API from the icinga section.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsConfig {
+    /// PEM file with additional CA certificates to trust
+    pub ca_file: Option<String>,
+    /// PEM files with the client certificate and its key, for certificate
+    /// based API authentication
+    pub client_cert: Option<(String, String)>,
+    /// SHA-256 fingerprints of accepted server certificates
+    pub fingerprints: Vec<[u8; 32]>,
+    /// Don't verify the certificate chain and host name, labs only
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsConfig {
+    pub fn is_default(&self) -> bool {
+        *self == TlsConfig::default()
+    }
+}
+
+/// Parse a SHA-256 fingerprint as printed by
+/// `openssl x509 -noout -fingerprint -sha256`, colons are optional.
+pub fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32], String> {
+    let hex: String = fingerprint.chars().filter(|&c| c != ':').collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(format!("'{}' is not a SHA-256 fingerprint", fingerprint));
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("'{}' is not a SHA-256 fingerprint", fingerprint))?;
+    }
+    Ok(bytes)
+}
+
+fn format_fingerprint(fingerprint: &[u8]) -> String {
+    fingerprint.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// With pinned fingerprints the server certificate has to be one of them
+/// and is trusted by itself, so a self-signed Icinga certificate works, but
+/// it still has to be for the host. Without pins it is verified against the
+/// trusted CAs. `insecure_skip_verify` turns off everything but the pins.
+#[derive(Debug)]
+struct PinningVerifier {
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    fingerprints: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.fingerprints.is_empty() {
+            let fingerprint = digest(&SHA256, end_entity.as_ref());
+            if !self.fingerprints.iter().any(|pinned| pinned[..] == *fingerprint.as_ref()) {
+                return Err(rustls::Error::General(format!(
+                    "server certificate fingerprint {} is not pinned",
+                    format_fingerprint(fingerprint.as_ref())
+                )));
+            }
+            if self.webpki.is_some() {
+                verify_server_name(&ParsedCertificate::try_from(end_entity)?, server_name)?;
+            }
+        } else if let Some(webpki) = &self.webpki {
+            webpki.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn verifier(tls: &TlsConfig, provider: &Arc<CryptoProvider>) -> Result<PinningVerifier, String> {
+    let webpki = if tls.insecure_skip_verify {
+        eprintln!("Warning: 'insecure_skip_verify' is set, the Icinga API certificate and host name aren't verified");
+        None
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(ca_file) = &tls.ca_file {
+            let certs = CertificateDer::pem_file_iter(ca_file)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("Failed to read 'ca_file' {}: {}", ca_file, e))?;
+            if certs.is_empty() {
+                return Err(format!("No certificates found in 'ca_file' {}", ca_file));
+            }
+            for cert in certs {
+                roots.add(cert).map_err(|e| format!("Invalid certificate in 'ca_file' {}: {}", ca_file, e))?;
+            }
+        }
+        Some(WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(provider))
+            .build()
+            .map_err(|e| format!("Failed to set up certificate verification: {}", e))?)
+    };
+    Ok(PinningVerifier { webpki, fingerprints: tls.fingerprints.clone(), provider: Arc::clone(provider) })
+}
+
+/// Build the rustls config for the API client from the TLS settings.
+pub fn client_config(tls: &TlsConfig) -> Result<ClientConfig, String> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let verifier = verifier(tls, &provider)?;
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+
+    match &tls.client_cert {
+        Some((cert_file, key_file)) => {
+            let certs = CertificateDer::pem_file_iter(cert_file)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("Failed to read 'client_cert' {}: {}", cert_file, e))?;
+            if certs.is_empty() {
+                return Err(format!("No certificates found in 'client_cert' {}", cert_file));
+            }
+            let key = PrivateKeyDer::from_pem_file(key_file)
+                .map_err(|e| format!("Failed to read 'client_key' {}: {}", key_file, e))?;
+            builder.with_client_auth_cert(certs, key)
+                .map_err(|e| format!("Invalid client certificate or key: {}", e))
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89";
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let fingerprint = parse_fingerprint(FINGERPRINT).unwrap();
+        assert_eq!(fingerprint[..4], [0xab, 0xcd, 0xef, 0x01]);
+        assert_eq!(format_fingerprint(&fingerprint), FINGERPRINT);
+        assert_eq!(parse_fingerprint(&FINGERPRINT.replace(':', "").to_lowercase()).unwrap(), fingerprint);
+
+        assert!(parse_fingerprint("AB:CD").is_err());
+        assert!(parse_fingerprint(&FINGERPRINT.replace("AB", "XY")).is_err());
+    }
+
+    #[test]
+    fn test_client_config_errors() {
+        let tls = TlsConfig { ca_file: Some("/nonexistent/ca.pem".to_string()), ..Default::default() };
+        assert!(client_config(&tls).unwrap_err().contains("'ca_file' /nonexistent/ca.pem"));
+
+        let tls = TlsConfig {
+            client_cert: Some(("/nonexistent/cert.pem".to_string(), "/nonexistent/key.pem".to_string())),
+            ..Default::default()
+        };
+        assert!(client_config(&tls).unwrap_err().contains("'client_cert'"));
+
+        let tls = TlsConfig { insecure_skip_verify: true, ..Default::default() };
+        assert!(client_config(&tls).is_ok());
+    }
+
+    /// Self-signed for icinga.example, valid until 2126.
+    const SELF_SIGNED: &str = "-----BEGIN CERTIFICATE-----
+MIIBpTCCAUqgAwIBAgIUAKSgEVrR2KYyb1o23G/hdIyu6cgwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOaWNpbmdhLmV4YW1wbGUwIBcNMjYxMDE5MDgwNTM2WhgPMjEy
+NjA5MjUwODA1MzZaMBkxFzAVBgNVBAMMDmljaW5nYS5leGFtcGxlMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAE+sjbVmbLw4tFHt2srCxzlDIGmMbTWD7lyaqkOwpD
+s6E1eTZGam84JvH2kLPSfpNrO1k7uhz6dnm2to1ehPUBbaNuMGwwHQYDVR0OBBYE
+FAi2NEcYoFWvIGV6GWNC9urGcuHSMB8GA1UdIwQYMBaAFAi2NEcYoFWvIGV6GWNC
+9urGcuHSMA8GA1UdEwEB/wQFMAMBAf8wGQYDVR0RBBIwEIIOaWNpbmdhLmV4YW1w
+bGUwCgYIKoZIzj0EAwIDSQAwRgIhAJw/kLnB9Xf2lyt0Yh2sFhA9vBoMyUp41OVh
+qwtAOb94AiEAtYkMpwMomVY6Z0n44Ow6qW0R1wyBSC46PH5Rb69KfLk=
+-----END CERTIFICATE-----
+";
+    const SELF_SIGNED_FINGERPRINT: &str =
+        "ED:22:4C:0A:B7:3E:9A:85:F2:8B:38:9C:79:79:FF:85:8F:E8:EC:81:C5:70:6E:83:6C:9D:BE:09:40:60:D4:C6";
+
+    #[test]
+    fn test_pinned_self_signed_certificate() {
+        let cert = CertificateDer::from_pem_slice(SELF_SIGNED.as_bytes()).unwrap();
+        let provider = Arc::new(crypto::ring::default_provider());
+        let verify = |tls: &TlsConfig, host: &'static str| {
+            let name = ServerName::try_from(host).unwrap();
+            verifier(tls, &provider).unwrap().verify_server_cert(&cert, &[], &name, &[], UnixTime::now()).is_ok()
+        };
+
+        let pinned = TlsConfig { fingerprints: vec![parse_fingerprint(SELF_SIGNED_FINGERPRINT).unwrap()], ..Default::default() };
+        assert!(verify(&pinned, "icinga.example"));
+        // The pin replaces the CA, not the host name check
+        assert!(!verify(&pinned, "other.example"));
+        assert!(!verify(&TlsConfig::default(), "icinga.example"));
+        let other_pin = TlsConfig { fingerprints: vec![[0xab; 32]], ..Default::default() };
+        assert!(!verify(&other_pin, "icinga.example"));
+
+        let insecure = TlsConfig { insecure_skip_verify: true, ..pinned };
+        assert!(verify(&insecure, "other.example"));
+        assert!(!verify(&TlsConfig { fingerprints: vec![[0xab; 32]], ..insecure }, "icinga.example"));
+    }
+}